@@ -0,0 +1,481 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::Scale;
+use crate::model::{EarResult, Session};
+
+/// Four-frequency pure-tone average.
+pub const PTA4_FREQS: [f32; 4] = [500.0, 1000.0, 2000.0, 4000.0];
+/// High-frequency average, where noise-induced loss shows first.
+pub const HFA_FREQS: [f32; 3] = [3000.0, 4000.0, 6000.0];
+/// Low-frequency average.
+pub const LFA_FREQS: [f32; 3] = [250.0, 500.0, 1000.0];
+
+/// Frequencies a noise notch is looked for at.
+pub const NOTCH_FREQS: [f32; 3] = [3000.0, 4000.0, 6000.0];
+/// A notch is measured against the better of these.
+pub const NOTCH_REFERENCE_FREQS: [f32; 2] = [1000.0, 2000.0];
+/// Hearing has to recover toward this frequency.
+pub const NOTCH_RECOVERY_FREQ: f32 = 8000.0;
+
+/// Degrees of hearing loss (ASHA, after Clark 1981): lower bound in dB HL and
+/// name.
+pub const ASHA_DEGREES: [(f64, &str); 7] = [
+    (-10.0, "normal"),
+    (16.0, "slight"),
+    (26.0, "mild"),
+    (41.0, "moderate"),
+    (56.0, "moderately severe"),
+    (71.0, "severe"),
+    (91.0, "profound"),
+];
+
+/// Grades of hearing loss of the WHO World report on hearing (2021), by the
+/// PTA4: lower bound in dB HL and name.
+pub const WHO_GRADES: [(f64, &str); 7] = [
+    (-10.0, "normal"),
+    (20.0, "mild"),
+    (35.0, "moderate"),
+    (50.0, "moderately severe"),
+    (65.0, "severe"),
+    (80.0, "profound"),
+    (95.0, "complete"),
+];
+
+/// Averages and classification of one transducer and ear.
+#[derive(PartialEq, Debug, Clone)]
+pub struct EarSummary {
+    /// Transducer and ear, as `EarResult::key`.
+    pub key: String,
+    /// `None` when one of the frequencies was not tested or not heard.
+    pub pta4: Option<f32>,
+    pub hfa: Option<f32>,
+    pub lfa: Option<f32>,
+    /// By the PTA4, only for air conduction in dB HL.
+    pub who: Option<&'static str>,
+    pub asha: Option<&'static str>,
+}
+
+impl EarSummary {
+    /// One line such as "headphone:R: PTA4 28.8, HFA 40.0, LFA 15.0 dB HL;
+    /// WHO mild, ASHA mild".
+    pub fn describe(&self, scale: Scale) -> String {
+        let average = |value: Option<f32>| match value {
+            Some(value) => format!("{:.1}", value),
+            None => "-".to_string(),
+        };
+        let mut line = format!(
+            "{}: PTA4 {}, HFA {}, LFA {} {}",
+            self.key,
+            average(self.pta4),
+            average(self.hfa),
+            average(self.lfa),
+            scale.unit()
+        );
+        if let (Some(who), Some(asha)) = (self.who, self.asha) {
+            line.push_str(&format!("; WHO {}, ASHA {}", who, asha));
+        }
+        line
+    }
+}
+
+/// The averages of every transducer and ear of `session`.
+pub fn summarize(session: &Session) -> Vec<EarSummary> {
+    session
+        .ears
+        .iter()
+        .map(|ear| {
+            let pta4 = average(ear, &PTA4_FREQS);
+            // degrees are defined for air conduction hearing levels
+            let classified = session.scale == Scale::HearingLevel && ear.transducer != "bone";
+            let class = |table: &[(f64, &'static str)]| {
+                pta4.filter(|_| classified)
+                    .map(|pta4| classify(table, pta4 as f64))
+            };
+            EarSummary {
+                key: ear.key(),
+                pta4,
+                hfa: average(ear, &HFA_FREQS),
+                lfa: average(ear, &LFA_FREQS),
+                who: class(&WHO_GRADES),
+                asha: class(&ASHA_DEGREES),
+            }
+        })
+        .collect()
+}
+
+/// Mean threshold of `ear` over `freqs`, if every one of them was tested and
+/// heard: a no-response level is only a lower bound of the threshold.
+pub fn average(ear: &EarResult, freqs: &[f32]) -> Option<f32> {
+    let mut sum = 0.0;
+    for freq in freqs {
+        sum += threshold(ear, *freq)?;
+    }
+    Some(sum / freqs.len() as f32)
+}
+
+/// The threshold of `ear` at `freq`, if it was tested and heard.
+fn threshold(ear: &EarResult, freq: f32) -> Option<f32> {
+    ear.points
+        .iter()
+        .find(|point| point.freq == freq && !point.no_response)
+        .map(|point| point.level)
+}
+
+/// Name of the last band of `table` whose lower bound `level` reaches; levels
+/// below the first bound count as its band.
+pub fn classify(table: &[(f64, &'static str)], level: f64) -> &'static str {
+    table
+        .iter()
+        .rev()
+        .find(|(lower, _)| level >= *lower)
+        .unwrap_or(&table[0])
+        .1
+}
+
+/// Criteria of the automatic findings, the `findings` section of
+/// `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct FindingCriteria {
+    #[serde(default)]
+    pub notch: NotchCriteria,
+    #[serde(default)]
+    pub asymmetry: AsymmetryCriteria,
+}
+
+/// The noise-induced notch: a threshold at 3, 4 or 6 kHz that is worse than
+/// the lower frequencies and recovers toward 8 kHz.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct NotchCriteria {
+    /// dB the notch is worse than the better of 1 and 2 kHz.
+    #[serde(default = "default_notch_depth")]
+    pub depth: f32,
+    /// dB the notch is worse than 8 kHz.
+    #[serde(default = "default_notch_recovery")]
+    pub recovery: f32,
+}
+
+fn default_notch_depth() -> f32 {
+    10.0
+}
+
+fn default_notch_recovery() -> f32 {
+    10.0
+}
+
+impl Default for NotchCriteria {
+    fn default() -> NotchCriteria {
+        NotchCriteria {
+            depth: default_notch_depth(),
+            recovery: default_notch_recovery(),
+        }
+    }
+}
+
+/// A difference between the ears of one transducer.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct AsymmetryCriteria {
+    /// dB one ear is worse than the other.
+    #[serde(default = "default_asymmetry_difference")]
+    pub difference: f32,
+    /// Number of adjacent tested frequencies the difference has to hold at,
+    /// with the same ear worse.
+    #[serde(default = "default_asymmetry_adjacent")]
+    pub adjacent: usize,
+}
+
+fn default_asymmetry_difference() -> f32 {
+    15.0
+}
+
+fn default_asymmetry_adjacent() -> usize {
+    2
+}
+
+impl Default for AsymmetryCriteria {
+    fn default() -> AsymmetryCriteria {
+        AsymmetryCriteria {
+            difference: default_asymmetry_difference(),
+            adjacent: default_asymmetry_adjacent(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FindingKind {
+    Notch,
+    Asymmetry,
+}
+
+/// A pattern in the thresholds that met its criteria.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Transducer and ear of a notch, transducer of an asymmetry.
+    pub key: String,
+    /// The notch frequency, or the frequencies the ears differ at.
+    pub freqs: Vec<f32>,
+    /// What was found, with the numbers.
+    pub detail: String,
+    /// The rule that fired, with the configured criteria.
+    pub rule: String,
+}
+
+impl Finding {
+    /// "<key>: <detail>", the rule left out.
+    pub fn describe(&self) -> String {
+        format!("{}: {}", self.key, self.detail)
+    }
+}
+
+/// Notches and asymmetries in `session`. Notches are only looked for in air
+/// conduction hearing levels, as dBFS thresholds include the frequency
+/// response of the headphone.
+pub fn findings(session: &Session, criteria: &FindingCriteria) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if session.scale == Scale::HearingLevel {
+        for ear in &session.ears {
+            if ear.transducer != "bone" {
+                findings.extend(notch(ear, &criteria.notch));
+            }
+        }
+    }
+    let mut transducers: Vec<&str> = Vec::new();
+    for ear in &session.ears {
+        if !transducers.contains(&ear.transducer.as_str()) {
+            transducers.push(&ear.transducer);
+        }
+    }
+    for transducer in transducers {
+        let ear = |side: &str| {
+            session
+                .ears
+                .iter()
+                .find(|ear| ear.transducer == transducer && ear.ear == side)
+        };
+        if let (Some(left), Some(right)) = (ear("L"), ear("R")) {
+            findings.extend(asymmetry(left, right, &criteria.asymmetry));
+        }
+    }
+    findings
+}
+
+/// The deepest notch of `ear` that meets `criteria`, if any. Frequencies
+/// without a response are left out.
+fn notch(ear: &EarResult, criteria: &NotchCriteria) -> Option<Finding> {
+    let level = |freq: f32| threshold(ear, freq);
+    let (reference_freq, reference) = NOTCH_REFERENCE_FREQS
+        .iter()
+        .filter_map(|freq| Some((*freq, level(*freq)?)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+    let recovery = level(NOTCH_RECOVERY_FREQ)?;
+    let (freq, bottom) = NOTCH_FREQS
+        .iter()
+        .filter_map(|freq| Some((*freq, level(*freq)?)))
+        .filter(|(_, bottom)| {
+            bottom - reference >= criteria.depth && bottom - recovery >= criteria.recovery
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    Some(Finding {
+        kind: FindingKind::Notch,
+        key: ear.key(),
+        freqs: vec![freq],
+        detail: format!(
+            "notch at {} Hz, {:.1} dB worse than {} Hz and {:.1} dB worse than {} Hz",
+            freq,
+            bottom - reference,
+            reference_freq,
+            bottom - recovery,
+            NOTCH_RECOVERY_FREQ
+        ),
+        rule: format!(
+            "notch: 3, 4 or 6 kHz >= {} dB worse than the better of 1 and 2 kHz \
+             and >= {} dB worse than 8 kHz",
+            criteria.depth, criteria.recovery
+        ),
+    })
+}
+
+/// Runs of adjacent frequencies, among those both ears were tested at and
+/// heard, where the same ear is worse by `criteria.difference` or more.
+fn asymmetry(left: &EarResult, right: &EarResult, criteria: &AsymmetryCriteria) -> Vec<Finding> {
+    let mut differences: Vec<(f32, f32)> = left
+        .points
+        .iter()
+        .filter(|point| !point.no_response)
+        .filter_map(|point| Some((point.freq, point.level - threshold(right, point.freq)?)))
+        .collect();
+    differences.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // the ear that is worse by enough, per frequency
+    let worse = |difference: f32| {
+        if difference >= criteria.difference {
+            Some("L")
+        } else if -difference >= criteria.difference {
+            Some("R")
+        } else {
+            None
+        }
+    };
+    let mut runs: Vec<(&str, Vec<(f32, f32)>)> = Vec::new();
+    let mut previous = None;
+    for (freq, difference) in differences {
+        let side = worse(difference);
+        match (side, runs.last_mut()) {
+            (Some(side), Some((last, run))) if previous == Some(side) && *last == side => {
+                run.push((freq, difference.abs()))
+            }
+            (Some(side), _) => runs.push((side, vec![(freq, difference.abs())])),
+            (None, _) => {}
+        }
+        previous = side;
+    }
+
+    let key = if left.transducer.is_empty() {
+        "L/R".to_string()
+    } else {
+        left.transducer.clone()
+    };
+    runs.into_iter()
+        .filter(|(_, run)| run.len() >= criteria.adjacent.max(1))
+        .map(|(side, run)| {
+            let freqs: Vec<f32> = run.iter().map(|(freq, _)| *freq).collect();
+            let largest = run.iter().map(|(_, d)| *d).fold(0.0, f32::max);
+            let smallest = run.iter().map(|(_, d)| *d).fold(f32::MAX, f32::min);
+            let names: Vec<String> = freqs.iter().map(|freq| freq.to_string()).collect();
+            Finding {
+                kind: FindingKind::Asymmetry,
+                key: key.clone(),
+                freqs,
+                detail: format!(
+                    "{} worse by {:.1}-{:.1} dB at {} Hz",
+                    side,
+                    smallest,
+                    largest,
+                    names.join(", ")
+                ),
+                rule: format!(
+                    "asymmetry: one ear >= {} dB worse at {} or more tested frequencies in a row",
+                    criteria.difference, criteria.adjacent
+                ),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ThresholdPoint;
+
+    #[test]
+    fn test_summarize() {
+        let mut session = Session::new("20240101_120000");
+        session.scale = Scale::HearingLevel;
+        for (transducer, levels) in [
+            ("headphone", [10.0, 15.0, 20.0, 35.0, 50.0, 45.0, 25.0]),
+            ("bone", [5.0, 10.0, 15.0, 30.0, 40.0, 40.0, 20.0]),
+        ] {
+            let mut ear = EarResult::new(transducer, "R");
+            ear.points = [250.0, 500.0, 1000.0, 2000.0, 4000.0, 6000.0, 8000.0]
+                .iter()
+                .zip(levels)
+                .map(|(freq, level)| ThresholdPoint::new(*freq, level))
+                .collect();
+            session.ears.push(ear);
+        }
+
+        let summaries = summarize(&session);
+        // no 3 kHz for the high-frequency average
+        assert_eq!(
+            summaries[0],
+            EarSummary {
+                key: "headphone:R".to_string(),
+                pta4: Some(30.0),
+                hfa: None,
+                lfa: Some(15.0),
+                who: Some("mild"),
+                asha: Some("mild"),
+            }
+        );
+        assert_eq!(summaries[1].who, None);
+        assert_eq!(
+            summaries[0].describe(session.scale),
+            "headphone:R: PTA4 30.0, HFA -, LFA 15.0 dB HL; WHO mild, ASHA mild"
+        );
+        assert_eq!(
+            summaries[1].describe(session.scale),
+            "bone:R: PTA4 23.8, HFA -, LFA 10.0 dB HL"
+        );
+
+        // no response at 4 kHz leaves the PTA4 and its degree open
+        session.ears[0].points[4].no_response = true;
+        let summaries = summarize(&session);
+        assert_eq!(summaries[0].pta4, None);
+        assert_eq!(summaries[0].asha, None);
+
+        assert_eq!(classify(&WHO_GRADES, 19.9), "normal");
+        assert_eq!(classify(&WHO_GRADES, 95.0), "complete");
+        assert_eq!(classify(&ASHA_DEGREES, 15.5), "normal");
+        assert_eq!(classify(&ASHA_DEGREES, -15.0), "normal");
+        assert_eq!(classify(&ASHA_DEGREES, 60.0), "moderately severe");
+    }
+
+    #[test]
+    fn test_findings() {
+        let ear = |side: &str, levels: [f32; 7]| {
+            let mut ear = EarResult::new("headphone", side);
+            ear.points = [500.0, 1000.0, 2000.0, 3000.0, 4000.0, 6000.0, 8000.0]
+                .iter()
+                .zip(levels)
+                .map(|(freq, level)| ThresholdPoint::new(*freq, level))
+                .collect();
+            ear
+        };
+        let mut session = Session::new("20240101_120000");
+        session.scale = Scale::HearingLevel;
+        session.ears = vec![
+            ear("L", [5.0, 5.0, 10.0, 30.0, 40.0, 35.0, 25.0]),
+            ear("R", [5.0, 5.0, 10.0, 10.0, 20.0, 15.0, 15.0]),
+        ];
+
+        let findings = findings(&session, &FindingCriteria::default());
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].kind, FindingKind::Notch);
+        assert_eq!(findings[0].freqs, [4000.0]);
+        assert_eq!(
+            findings[0].describe(),
+            "headphone:L: notch at 4000 Hz, 35.0 dB worse than 1000 Hz and 15.0 dB worse \
+             than 8000 Hz"
+        );
+        assert_eq!(
+            findings[0].rule,
+            "notch: 3, 4 or 6 kHz >= 10 dB worse than the better of 1 and 2 kHz and >= 10 dB \
+             worse than 8 kHz"
+        );
+        // R recovers by 5 dB only, and 8 kHz differs by 10 dB
+        assert_eq!(findings[1].kind, FindingKind::Asymmetry);
+        assert_eq!(findings[1].freqs, [3000.0, 4000.0, 6000.0]);
+        assert_eq!(
+            findings[1].describe(),
+            "headphone: L worse by 20.0-20.0 dB at 3000, 4000, 6000 Hz"
+        );
+
+        let criteria = FindingCriteria {
+            notch: NotchCriteria {
+                depth: 40.0,
+                recovery: 10.0,
+            },
+            asymmetry: AsymmetryCriteria {
+                difference: 15.0,
+                adjacent: 4,
+            },
+        };
+        assert!(super::findings(&session, &criteria).is_empty());
+        // dBFS thresholds are not checked for notches
+        session.scale = Scale::Dbfs;
+        assert_eq!(
+            super::findings(&session, &FindingCriteria::default())[0].kind,
+            FindingKind::Asymmetry
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::io::{stdin, stdout, Write};
+
+use crate::calibration::{Calibration, CalibrationProfile, Offsets, Retspl};
+use crate::channel_map::channel_count;
+use crate::stimulus::Stimulus;
+use crate::Point;
+
+/// Level of the reference tones in dBFS.
+pub const REFERENCE_LEVEL: f64 = -20.0;
+
+/// Interactive calibration: plays a steady reference tone at every
+/// `config.test_freq` on each ear's output of the first transducer of the
+/// channel map, asks for the SPL read from a sound level meter or coupler and
+/// saves the resulting offsets as the profile `name`. A transducer with a
+/// single output is measured once, for both ears. Offsets are keyed by whole
+/// Hz, so other test frequencies are refused, and the end of input aborts.
+pub fn run(config: &Point, name: &str, dir_path: &str) -> Result<(), String> {
+    if let Some(freq) = config.test_freq.iter().find(|freq| freq.fract() != 0.0) {
+        return Err(format!(
+            "cannot calibrate {} Hz: calibration offsets are kept per whole Hz",
+            freq
+        ));
+    }
+    let transducer = &config.channel_map[0];
+    let channels = channel_count(&config.channel_map);
+    let mut output = config.output.open(name, channels)?;
+
+    println!("Calibration profile: {}", name);
+    println!("Set the OS and interface volume to the value you will test with, and keep it.");
+    let device = prompt("Output device name: ")?;
+    let os_volume = prompt("OS volume setting (e.g. -47.0): ")?;
+    let headphone = prompt("Headphone model: ")?;
+    let retspl = loop {
+        let answer = prompt("RETSPL table (tdh39 | er3a | er3a_ha2): ")?;
+        match serde_yaml::from_str::<Retspl>(&answer) {
+            Ok(retspl @ Retspl::Standard(_)) => break retspl,
+            _ => println!("Unknown table {:?}.", answer),
+        }
+    };
+
+    let mut measure = |channel: u16| {
+        let mut offsets = BTreeMap::new();
+        for freq in &config.test_freq {
+            output.play(
+                &Stimulus::continuous(*freq, channel, channels),
+                REFERENCE_LEVEL,
+            );
+            let spl = loop {
+                let answer = prompt(&format!("{} Hz: measured level [dB SPL]: ", freq));
+                match answer.map(|answer| answer.parse::<f64>()) {
+                    Ok(Ok(spl)) => break spl,
+                    Ok(Err(_)) => println!("Please enter a number."),
+                    Err(message) => {
+                        output.stop();
+                        return Err(message);
+                    }
+                }
+            };
+            output.stop();
+            offsets.insert(*freq as i32, spl - REFERENCE_LEVEL);
+        }
+        Ok(offsets)
+    };
+    let offsets = if transducer.channels >= 2 {
+        let mut tables = BTreeMap::new();
+        for (ear, side) in [("L", "left"), ("R", "right")] {
+            println!(
+                "Put the {} earphone on the coupler. Each tone plays at {} dBFS until you enter the reading.",
+                side, REFERENCE_LEVEL
+            );
+            tables.insert(ear.to_string(), measure(transducer.channel_of(ear))?);
+        }
+        Offsets::PerEar(tables)
+    } else {
+        println!(
+            "Put the {} on the coupler. Each tone plays at {} dBFS until you enter the reading.",
+            transducer.transducer, REFERENCE_LEVEL
+        );
+        Offsets::Both(measure(transducer.channel)?)
+    };
+
+    let profile = CalibrationProfile {
+        name: name.to_string(),
+        device,
+        os_volume,
+        created: chrono::Local::now().to_rfc3339(),
+        reference_level: REFERENCE_LEVEL,
+        calibration: Calibration {
+            headphone,
+            transducer: transducer.transducer.clone(),
+            retspl,
+            offsets,
+        },
+    };
+    profile.save(dir_path);
+    println!(
+        "Saved {}",
+        CalibrationProfile::path(dir_path, name).display()
+    );
+    Ok(())
+}
+
+/// Reads one answer. The end of input aborts the calibration.
+fn prompt(message: &str) -> Result<String, String> {
+    print!("{}", message);
+    stdout().flush().unwrap();
+    let mut line = String::new();
+    if stdin().read_line(&mut line).unwrap() == 0 {
+        println!();
+        return Err("calibration aborted: end of input".to_string());
+    }
+    Ok(line.trim().to_string())
+}
@@ -0,0 +1,490 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::model::Session;
+
+/// Reference equivalent threshold sound pressure levels (dB re 20 µPa):
+/// the SPL a transducer must produce for a tone to sit at 0 dB HL.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum Retspl {
+    Standard(StandardRetspl),
+    /// Frequency (Hz) -> RETSPL (dB SPL), e.g. from the headphone manufacturer.
+    Custom(#[serde(deserialize_with = "deserialize_table")] BTreeMap<i32, f64>),
+}
+
+/// RETSPL tables from the ISO 389 series.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum StandardRetspl {
+    /// ISO 389-1, supra-aural Telephonics TDH 39 on an IEC 60318-3 coupler.
+    Tdh39,
+    /// ISO 389-2, insert earphone Etymotic ER-3A on an IEC 60711 occluded-ear simulator.
+    Er3a,
+    /// ISO 389-2, insert earphone Etymotic ER-3A on an HA-2 coupler with rigid tube.
+    Er3aHa2,
+}
+
+const TDH39: [(i32, f64); 11] = [
+    (125, 45.0),
+    (250, 25.5),
+    (500, 11.5),
+    (750, 8.0),
+    (1000, 7.0),
+    (1500, 6.5),
+    (2000, 9.0),
+    (3000, 10.0),
+    (4000, 9.5),
+    (6000, 15.5),
+    (8000, 13.0),
+];
+
+const ER3A: [(i32, f64); 11] = [
+    (125, 28.0),
+    (250, 17.5),
+    (500, 9.5),
+    (750, 6.0),
+    (1000, 5.5),
+    (1500, 9.5),
+    (2000, 11.5),
+    (3000, 13.0),
+    (4000, 15.0),
+    (6000, 16.0),
+    (8000, 15.5),
+];
+
+const ER3A_HA2: [(i32, f64); 11] = [
+    (125, 26.0),
+    (250, 14.0),
+    (500, 5.5),
+    (750, 2.0),
+    (1000, 0.0),
+    (1500, 2.0),
+    (2000, 3.0),
+    (3000, 3.5),
+    (4000, 5.5),
+    (6000, 2.0),
+    (8000, 0.0),
+];
+
+impl Retspl {
+    pub fn table(&self) -> BTreeMap<i32, f64> {
+        match self {
+            Retspl::Standard(StandardRetspl::Tdh39) => TDH39.into_iter().collect(),
+            Retspl::Standard(StandardRetspl::Er3a) => ER3A.into_iter().collect(),
+            Retspl::Standard(StandardRetspl::Er3aHa2) => ER3A_HA2.into_iter().collect(),
+            Retspl::Custom(table) => table.clone(),
+        }
+    }
+
+    /// Where the table comes from, for reports.
+    pub fn source(&self) -> String {
+        match self {
+            Retspl::Standard(StandardRetspl::Tdh39) => "ISO 389-1, TDH 39".to_string(),
+            Retspl::Standard(StandardRetspl::Er3a) => "ISO 389-2, ER-3A".to_string(),
+            Retspl::Standard(StandardRetspl::Er3aHa2) => "ISO 389-2, ER-3A on HA-2".to_string(),
+            Retspl::Custom(table) => format!("custom table, {} frequencies", table.len()),
+        }
+    }
+}
+
+/// Frequency (Hz) -> measured dB SPL of a 0 dBFS tone.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum Offsets {
+    /// One table for both ears, e.g. for a single output moved between them.
+    Both(#[serde(deserialize_with = "deserialize_table")] BTreeMap<i32, f64>),
+    /// A table per ear ("L", "R"), each measured through its own channel.
+    PerEar(#[serde(deserialize_with = "deserialize_tables")] BTreeMap<String, BTreeMap<i32, f64>>),
+}
+
+impl Offsets {
+    /// The table for `ear`, if it was measured.
+    pub fn table(&self, ear: &str) -> Option<&BTreeMap<i32, f64>> {
+        match self {
+            Offsets::Both(table) => Some(table),
+            Offsets::PerEar(tables) => tables.get(ear),
+        }
+    }
+
+    /// Every table with the ear it belongs to, `None` for `Both`.
+    pub fn tables(&self) -> Vec<(Option<&str>, &BTreeMap<i32, f64>)> {
+        match self {
+            Offsets::Both(table) => vec![(None, table)],
+            Offsets::PerEar(tables) => tables
+                .iter()
+                .map(|(ear, table)| (Some(ear.as_str()), table))
+                .collect(),
+        }
+    }
+}
+
+/// Everything needed to turn a digital level (dBFS) into hearing level (dB HL)
+/// for one headphone on one output chain.
+///
+/// `dB HL = dBFS + offset(ear, freq) - RETSPL(freq)`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Calibration {
+    /// Headphone model the offsets were measured with.
+    pub headphone: String,
+    /// Channel map transducer the headphone is wired to. Results of other
+    /// transducers cannot be converted with it.
+    #[serde(default = "default_transducer")]
+    pub transducer: String,
+    pub retspl: Retspl,
+    pub offsets: Offsets,
+}
+
+fn default_transducer() -> String {
+    "headphone".to_string()
+}
+
+impl Calibration {
+    /// `None` outside the frequencies measured on `ear`.
+    pub fn dbfs_to_spl(&self, ear: &str, freq: f32, dbfs: f64) -> Option<f64> {
+        Some(dbfs + interpolate_within(self.offsets.table(ear)?, freq)?)
+    }
+
+    /// `None` outside the frequencies of the offsets or the RETSPL table.
+    pub fn dbfs_to_hl(&self, ear: &str, freq: f32, dbfs: f64) -> Option<f64> {
+        let retspl = interpolate_within(&self.retspl.table(), freq)?;
+        Some(self.dbfs_to_spl(ear, freq, dbfs)? - retspl)
+    }
+
+    /// Converts a whole session from dBFS to dB HL and records this
+    /// calibration in it. Masker levels stay in dBFS. Fails, leaving the
+    /// session as it is, when a threshold lies outside the calibrated range or
+    /// was measured with another transducer.
+    pub fn to_hearing_level(&self, session: &Session) -> Result<Session, String> {
+        assert_eq!(session.scale, Scale::Dbfs);
+        let mut session = session.clone();
+        for ear in &mut session.ears {
+            if ear.transducer != self.transducer {
+                return Err(format!(
+                    "{} is calibrated for {}, not {}",
+                    self.headphone, self.transducer, ear.transducer
+                ));
+            }
+            if self.offsets.table(&ear.ear).is_none() {
+                return Err(format!(
+                    "{} has no offsets for ear {}",
+                    self.headphone, ear.ear
+                ));
+            }
+            for point in &mut ear.points {
+                let freq = point.freq;
+                let to_hl = |level: f32| {
+                    self.dbfs_to_hl(&ear.ear, freq, level as f64)
+                        .map(|level| level as f32)
+                        .ok_or_else(|| {
+                            format!(
+                                "{} Hz is outside the calibrated range of {}",
+                                freq, self.headphone
+                            )
+                        })
+                };
+                point.level = to_hl(point.level)?;
+                for trial in &mut point.trials {
+                    trial.level = to_hl(trial.level)?;
+                }
+            }
+        }
+        session.scale = Scale::HearingLevel;
+        session.calibration = Some(self.clone());
+        session.calibration_fingerprint = Some(self.fingerprint());
+        Ok(session)
+    }
+
+    /// Short identifier of this exact calibration: the 64-bit FNV-1a hash of
+    /// its YAML, in hex. Any change to a table gives a new one.
+    pub fn fingerprint(&self) -> String {
+        let yaml = serde_yaml::to_string(self).unwrap();
+        let hash = yaml.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        format!("{:016x}", hash)
+    }
+}
+
+/// A named calibration saved by the `calibrate` wizard, stored as
+/// `<dir>/<name>.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CalibrationProfile {
+    pub name: String,
+    /// Output device the offsets were measured on.
+    pub device: String,
+    /// OS / interface volume setting the offsets are only valid for.
+    pub os_volume: String,
+    /// RFC 3339 timestamp of the measurement.
+    pub created: String,
+    /// dBFS level of the reference tones played during the measurement.
+    pub reference_level: f64,
+    #[serde(flatten)]
+    pub calibration: Calibration,
+}
+
+impl CalibrationProfile {
+    pub fn path(dir_path: &str, name: &str) -> std::path::PathBuf {
+        Path::new(dir_path).join(format!("{}.yaml", name))
+    }
+
+    pub fn save(&self, dir_path: &str) {
+        if fs::metadata(dir_path).is_err() {
+            fs::create_dir_all(dir_path).unwrap();
+        }
+        let yaml = serde_yaml::to_string(self).unwrap();
+        fs::write(Self::path(dir_path, &self.name), yaml).unwrap();
+    }
+
+    /// Loads the profile `name`, refusing profiles older than `max_age_days`.
+    pub fn load(
+        dir_path: &str,
+        name: &str,
+        max_age_days: Option<f64>,
+    ) -> Result<CalibrationProfile, String> {
+        let path = Self::path(dir_path, name);
+        let yaml = fs::read_to_string(&path).map_err(|_| {
+            format!(
+                "calibration profile {:?} not found at {}. Run `hearing-test calibrate --profile {}` first.",
+                name,
+                path.display(),
+                name
+            )
+        })?;
+        let profile: CalibrationProfile = serde_yaml::from_str(&yaml)
+            .map_err(|e| format!("invalid calibration profile {}: {}", path.display(), e))?;
+
+        if let Some(max_age_days) = max_age_days {
+            let age_days = profile.age_days()?;
+            if age_days > max_age_days {
+                return Err(format!(
+                    "calibration profile {:?} is {:.1} days old (max. {} days). Please recalibrate.",
+                    name, age_days, max_age_days
+                ));
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn age_days(&self) -> Result<f64, String> {
+        let created = chrono::DateTime::parse_from_rfc3339(&self.created)
+            .map_err(|e| format!("invalid created date {:?}: {}", self.created, e))?;
+        let age = chrono::Local::now().signed_duration_since(created);
+        Ok(age.num_seconds() as f64 / 86400.0)
+    }
+}
+
+/// Unit of the levels in a session.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scale {
+    /// Digital level relative to the `SineWave` peak.
+    Dbfs,
+    /// Hearing level, after calibration.
+    HearingLevel,
+}
+
+impl Scale {
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Scale::Dbfs => "dBFS",
+            Scale::HearingLevel => "dB HL",
+        }
+    }
+}
+
+/// Value at `freq`, interpolated linearly over log frequency between the points
+/// of `table`, or `None` outside them.
+pub fn interpolate_within(table: &BTreeMap<i32, f64>, freq: f32) -> Option<f64> {
+    let (first, last) = (table.keys().next()?, table.keys().next_back()?);
+    if freq < *first as f32 || freq > *last as f32 {
+        return None;
+    }
+    Some(interpolate(table, freq))
+}
+
+/// Value at `freq`, interpolated linearly over log frequency between the points
+/// of `table` and held flat beyond them.
+pub fn interpolate(table: &BTreeMap<i32, f64>, freq: f32) -> f64 {
+    let freq = freq as f64;
+    let below = table.iter().rev().find(|(f, _)| **f as f64 <= freq);
+    let above = table.iter().find(|(f, _)| **f as f64 >= freq);
+    match (below, above) {
+        (Some((f0, v0)), Some((f1, v1))) if f0 != f1 => {
+            let x = (freq.ln() - (*f0 as f64).ln()) / ((*f1 as f64).ln() - (*f0 as f64).ln());
+            v0 + (v1 - v0) * x
+        }
+        (Some((_, v)), _) | (None, Some((_, v))) => *v,
+        (None, None) => panic!("cannot interpolate an empty table"),
+    }
+}
+
+/// Frequency key of a table: a number in YAML, a string in JSON.
+#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+enum FreqKey {
+    Hz(i32),
+    Text(String),
+}
+
+fn to_table<E: de::Error>(table: BTreeMap<FreqKey, f64>) -> Result<BTreeMap<i32, f64>, E> {
+    if table.is_empty() {
+        return Err(E::custom("empty frequency table"));
+    }
+    table
+        .into_iter()
+        .map(|(key, value)| match key {
+            FreqKey::Hz(freq) => Ok((freq, value)),
+            FreqKey::Text(text) => text
+                .parse()
+                .map(|freq| (freq, value))
+                .map_err(|_| E::custom(format!("invalid frequency {:?}", text))),
+        })
+        .collect()
+}
+
+/// Deserializes a frequency table whether its keys are numbers or strings.
+/// Empty tables are rejected.
+///
+/// Serde hands the keys of maps inside tagged or untagged enums over as
+/// strings when they come from JSON, which a plain `BTreeMap<i32, f64>`
+/// rejects.
+pub fn deserialize_table<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<i32, f64>, D::Error> {
+    to_table(BTreeMap::deserialize(deserializer)?)
+}
+
+/// `deserialize_table` for tables keyed by ear. There must be at least one.
+pub fn deserialize_tables<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, BTreeMap<i32, f64>>, D::Error> {
+    let tables = BTreeMap::<String, BTreeMap<FreqKey, f64>>::deserialize(deserializer)?;
+    if tables.is_empty() {
+        return Err(de::Error::custom("no frequency tables"));
+    }
+    tables
+        .into_iter()
+        .map(|(key, table)| Ok((key, to_table(table)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{EarResult, ThresholdPoint};
+
+    #[test]
+    fn test_interpolate() {
+        let table: BTreeMap<i32, f64> = [(1000, 0.0), (4000, 20.0)].into_iter().collect();
+        assert_eq!(interpolate(&table, 1000.0), 0.0);
+        assert!((interpolate(&table, 2000.0) - 10.0).abs() < 1e-9);
+        assert_eq!(interpolate(&table, 500.0), 0.0);
+        assert_eq!(interpolate(&table, 8000.0), 20.0);
+        assert_eq!(interpolate_within(&table, 1000.0), Some(0.0));
+        assert_eq!(interpolate_within(&table, 500.0), None);
+        assert_eq!(interpolate_within(&table, 8000.0), None);
+        assert_eq!(interpolate_within(&BTreeMap::new(), 1000.0), None);
+    }
+
+    #[test]
+    fn test_dbfs_to_hl() {
+        let calibration: Calibration = serde_yaml::from_str(
+            "
+headphone: TDH 39
+retspl: tdh39
+offsets: {1000: 100.0, 4000: 95.0}
+",
+        )
+        .unwrap();
+        // -80 dBFS at 1 kHz -> 20 dB SPL -> 13 dB HL
+        assert!((calibration.dbfs_to_hl("L", 1000.0, -80.0).unwrap() - 13.0).abs() < 1e-9);
+        // -80 dBFS at 4 kHz -> 15 dB SPL -> 5.5 dB HL
+        assert!((calibration.dbfs_to_hl("R", 4000.0, -80.0).unwrap() - 5.5).abs() < 1e-9);
+        // no offset was measured at 8 kHz
+        assert_eq!(calibration.dbfs_to_hl("L", 8000.0, -80.0), None);
+
+        let mut session = Session::new("20240101_120000");
+        let mut ear = EarResult::new("headphone", "L");
+        ear.points = vec![ThresholdPoint::new(1000.0, -80.0)];
+        session.ears.push(ear.clone());
+        let converted = calibration.to_hearing_level(&session).unwrap();
+        assert_eq!(converted.scale, Scale::HearingLevel);
+        assert_eq!(
+            converted.calibration_fingerprint,
+            Some(calibration.fingerprint())
+        );
+        assert!((converted.ears[0].points[0].level - 13.0).abs() < 1e-4);
+        ear.points.push(ThresholdPoint::new(8000.0, -80.0));
+        session.ears = vec![ear];
+        assert_eq!(
+            calibration.to_hearing_level(&session),
+            Err("8000 Hz is outside the calibrated range of TDH 39".to_string())
+        );
+        let mut bone = EarResult::new("bone", "L");
+        bone.points = vec![ThresholdPoint::new(1000.0, -80.0)];
+        session.ears = vec![bone];
+        assert_eq!(
+            calibration.to_hearing_level(&session),
+            Err("TDH 39 is calibrated for headphone, not bone".to_string())
+        );
+
+        let empty = "headphone: TDH 39\nretspl: tdh39\noffsets: {}\n";
+        assert!(serde_yaml::from_str::<Calibration>(empty).is_err());
+
+        // each ear through its own channel
+        let calibration: Calibration = serde_yaml::from_str(
+            "
+headphone: TDH 39
+retspl: tdh39
+offsets: {L: {1000: 100.0}, R: {1000: 97.0}}
+",
+        )
+        .unwrap();
+        assert!((calibration.dbfs_to_hl("L", 1000.0, -80.0).unwrap() - 13.0).abs() < 1e-9);
+        assert!((calibration.dbfs_to_hl("R", 1000.0, -80.0).unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(calibration.dbfs_to_hl("", 1000.0, -80.0), None);
+    }
+
+    #[test]
+    fn test_profile_age() {
+        let dir = std::env::temp_dir().join("hearing-test-calibration-profile");
+        let dir_path = dir.to_str().unwrap();
+        let mut profile = CalibrationProfile {
+            name: "lab".to_string(),
+            device: "USB DAC".to_string(),
+            os_volume: "-47.0".to_string(),
+            created: chrono::Local::now().to_rfc3339(),
+            reference_level: -20.0,
+            calibration: Calibration {
+                headphone: "TDH 39".to_string(),
+                transducer: "headphone".to_string(),
+                retspl: Retspl::Standard(StandardRetspl::Tdh39),
+                offsets: Offsets::Both([(1000, 100.0)].into_iter().collect()),
+            },
+        };
+        profile.save(dir_path);
+        assert_eq!(
+            CalibrationProfile::load(dir_path, "lab", Some(1.0)),
+            Ok(profile.clone())
+        );
+        assert!(CalibrationProfile::load(dir_path, "missing", None).is_err());
+
+        profile.created = (chrono::Local::now() - chrono::Duration::days(40)).to_rfc3339();
+        profile.save(dir_path);
+        assert!(CalibrationProfile::load(dir_path, "lab", Some(30.0)).is_err());
+        assert!(CalibrationProfile::load(dir_path, "lab", None).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_custom_retspl() {
+        let retspl: Retspl = serde_yaml::from_str("{1000: 3.0}").unwrap();
+        assert_eq!(retspl.table().get(&1000), Some(&3.0));
+        let retspl: Retspl = serde_yaml::from_str("er3a_ha2").unwrap();
+        assert_eq!(retspl, Retspl::Standard(StandardRetspl::Er3aHa2));
+    }
+}
@@ -0,0 +1,120 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+/// The ears, in the default test order.
+pub const EARS: [&str; 2] = ["L", "R"];
+
+/// Order in which the ears of every transducer are tested.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EarOrder {
+    /// As listed in `ears`.
+    #[default]
+    Listed,
+    /// Shuffled for every transducer, so the better ear is not always first.
+    Random,
+}
+
+impl EarOrder {
+    /// `ears` in the order to test them.
+    pub fn arrange(&self, ears: &[String]) -> Vec<String> {
+        let mut ears = ears.to_vec();
+        if *self == EarOrder::Random {
+            ears.shuffle(&mut rand::thread_rng());
+        }
+        ears
+    }
+}
+
+/// One entry of the channel map in `config.yaml`: a transducer and the output
+/// channels it is wired to.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Transducer {
+    /// Name used in the results, e.g. "headphone", "bone" or "speaker".
+    pub transducer: String,
+    /// First output channel, counting from 0.
+    pub channel: u16,
+    /// 2: left ear on `channel`, right ear on `channel + 1`.
+    /// 1: a single output used for both ears, e.g. a bone conductor that is
+    /// moved from one mastoid to the other.
+    #[serde(default = "default_channels")]
+    pub channels: u16,
+}
+
+fn default_channels() -> u16 {
+    2
+}
+
+impl Transducer {
+    /// Output channel that reaches `ear`.
+    pub fn channel_of(&self, ear: &str) -> u16 {
+        if self.channels >= 2 && ear == "R" {
+            self.channel + 1
+        } else {
+            self.channel
+        }
+    }
+}
+
+/// Both ears, left first.
+pub fn default_ears() -> Vec<String> {
+    EARS.iter().map(|ear| ear.to_string()).collect()
+}
+
+/// Stereo headphones on the first two outputs.
+pub fn default_channel_map() -> Vec<Transducer> {
+    vec![Transducer {
+        transducer: "headphone".to_string(),
+        channel: 0,
+        channels: 2,
+    }]
+}
+
+/// Number of output channels needed to drive every transducer of `map`.
+pub fn channel_count(map: &[Transducer]) -> u16 {
+    map.iter()
+        .map(|transducer| transducer.channel + transducer.channels)
+        .max()
+        .unwrap_or(2)
+}
+
+/// Splits a result key (see `EarResult::key`) into transducer and ear. Keys
+/// without a transducer, e.g. "L" in results saved before the channel map,
+/// give an empty transducer.
+pub fn split_key(key: &str) -> (&str, &str) {
+    key.rsplit_once(':').unwrap_or(("", key))
+}
+
+pub fn other_ear(ear: &str) -> &'static str {
+    if ear == "L" {
+        "R"
+    } else {
+        "L"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_map_from_yaml() {
+        let map: Vec<Transducer> = serde_yaml::from_str(
+            "
+- {transducer: headphone, channel: 0}
+- {transducer: bone, channel: 2, channels: 1}
+- {transducer: speaker, channel: 6, channels: 2}
+",
+        )
+        .unwrap();
+        assert_eq!(map[0].channel_of("L"), 0);
+        assert_eq!(map[0].channel_of("R"), 1);
+        assert_eq!(map[1].channel_of("L"), 2);
+        assert_eq!(map[1].channel_of("R"), 2);
+        assert_eq!(map[2].channel_of("R"), 7);
+        assert_eq!(channel_count(&map), 8);
+
+        assert_eq!(split_key("bone:R"), ("bone", "R"));
+        assert_eq!(split_key("L"), ("", "L"));
+    }
+}
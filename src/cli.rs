@@ -0,0 +1,186 @@
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::plot::PlotFormat;
+
+/// Pure-tone hearing test.
+///
+/// Without a subcommand the test is run, as with `run`.
+#[derive(Parser, Debug)]
+#[command(name = "hearing-test", version)]
+pub struct Cli {
+    /// Config file.
+    #[arg(long, global = true, default_value = "config.yaml")]
+    pub config: PathBuf,
+    /// Directory of the results, plots, session exports and history database.
+    #[arg(long, global = true, default_value = "./result")]
+    pub output_dir: String,
+    /// Directory of the calibration profiles.
+    #[arg(long, global = true, default_value = "./calibration")]
+    pub calibration_dir: String,
+    /// Format of the audiogram instead of `plot.format`: png, svg or pdf.
+    #[arg(long, global = true, value_parser = parse_format)]
+    pub plot_format: Option<PlotFormat>,
+    /// Size of the audiogram instead of `plot.size`, e.g. `--plot-size 1200x900`;
+    /// in points for PDF.
+    #[arg(long, global = true, value_parser = parse_size)]
+    pub plot_size: Option<(u32, u32)>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the hearing test.
+    Run(RunArgs),
+    /// Measure the output level of every test frequency and save it as a
+    /// calibration profile.
+    Calibrate {
+        /// Name of the profile.
+        #[arg(long, default_value = "default")]
+        profile: String,
+    },
+    /// Draw the audiogram and staircase of a session again.
+    Plot {
+        /// Session export file, or the date of a stored session.
+        session: String,
+    },
+    /// List the stored sessions.
+    History {
+        /// Only the sessions of this subject.
+        #[arg(long)]
+        subject: Option<String>,
+        /// Also draw the thresholds over time into `history.png` of the result
+        /// directory and list the significant shifts.
+        #[arg(long)]
+        plot: bool,
+    },
+    /// Write a session as JSON or YAML, chosen by the extension of `output`.
+    Export {
+        /// Session export file, or the date of a stored session.
+        session: String,
+        output: PathBuf,
+    },
+    /// Add a session export from another machine to the results.
+    Import {
+        /// Session export file (.json, .yaml or .yml).
+        session: PathBuf,
+    },
+    /// Import the `result_*.csv` files of a directory into the history database.
+    ImportCsv {
+        /// Defaults to the output directory.
+        dir: Option<String>,
+        /// Subject of the sessions that do not name one.
+        #[arg(long)]
+        subject: Option<String>,
+    },
+    /// List the subject registry.
+    Subjects,
+    /// Check the config file without running the test.
+    ValidateConfig,
+}
+
+#[derive(Args, Debug, Default, Clone)]
+pub struct RunArgs {
+    /// Test only this ear.
+    #[arg(long, value_parser = ["L", "R"])]
+    pub ear: Option<String>,
+    /// Test these frequencies instead of `test_freq`, e.g. `--freqs 1000,2000,4000`.
+    #[arg(long, value_delimiter = ',')]
+    pub freqs: Option<Vec<f32>>,
+    /// Calibration profile to use instead of `calibration_profile`.
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Subject ID; registered on first use.
+    #[arg(long)]
+    pub subject: Option<String>,
+    /// Name or pseudonym of the subject.
+    #[arg(long, requires = "subject")]
+    pub name: Option<String>,
+    #[arg(long, requires = "subject")]
+    pub birth_year: Option<u32>,
+    #[arg(long, requires = "subject")]
+    pub notes: Option<String>,
+}
+
+/// A `plot.format` value.
+fn parse_format(value: &str) -> Result<PlotFormat, String> {
+    match value {
+        "png" => Ok(PlotFormat::Png),
+        "svg" => Ok(PlotFormat::Svg),
+        "pdf" => Ok(PlotFormat::Pdf),
+        _ => Err(format!("expected png, svg or pdf, got {:?}", value)),
+    }
+}
+
+/// `<width>x<height>`, both positive.
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let error = || format!("expected <width>x<height>, e.g. 1200x900, got {:?}", value);
+    let (width, height) = value.split_once('x').ok_or_else(error)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["hearing-test", "--subject", "S01", "--freqs", "1000,4000"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.run.subject.as_deref(), Some("S01"));
+        assert_eq!(cli.run.freqs, Some(vec![1000.0, 4000.0]));
+
+        let cli = Cli::parse_from([
+            "hearing-test",
+            "run",
+            "--ear",
+            "R",
+            "--profile",
+            "lab",
+            "--output-dir",
+            "/tmp/results",
+        ]);
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("expected run");
+        };
+        assert_eq!(run.ear.as_deref(), Some("R"));
+        assert_eq!(run.profile.as_deref(), Some("lab"));
+        assert_eq!(cli.output_dir, "/tmp/results");
+
+        let cli = Cli::parse_from(["hearing-test", "--output-dir", "/tmp/results", "history"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::History {
+                subject: None,
+                plot: false
+            })
+        ));
+        assert_eq!(cli.output_dir, "/tmp/results");
+
+        let cli = Cli::parse_from([
+            "hearing-test",
+            "plot",
+            "20240101_120000",
+            "--plot-format",
+            "pdf",
+            "--plot-size",
+            "842x595",
+        ]);
+        assert_eq!(cli.plot_format, Some(PlotFormat::Pdf));
+        assert_eq!(cli.plot_size, Some((842, 595)));
+        assert!(Cli::try_parse_from(["hearing-test", "--plot-size", "800"]).is_err());
+        assert!(Cli::try_parse_from(["hearing-test", "--plot-size", "0x600"]).is_err());
+
+        assert!(Cli::try_parse_from(["hearing-test", "run", "--ear", "X"]).is_err());
+        assert!(Cli::try_parse_from(["hearing-test", "--name", "Heron"]).is_err());
+    }
+}
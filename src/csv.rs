@@ -1,305 +1,615 @@
-use std::collections::BTreeMap;
-use std::fs;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::analysis;
+use crate::calibration::Scale;
+use crate::channel_map::split_key;
+use crate::model::{EarResult, Session, ThresholdPoint};
+use crate::procedure::Procedure;
+
+/// Number of previous versions kept of a result file, as
+/// `result_NN.csv.bak.1` (newest) to `result_NN.csv.bak.3`.
+pub const BACKUPS: usize = 3;
 
 pub fn check_exist_csv(dir_path: &str) -> bool {
     let path = Path::new(dir_path);
     if !path.exists() {
-        fs::create_dir(dir_path).unwrap();
+        fs::create_dir_all(path).unwrap();
     }
 
     // check exist result_[0-9]{2}.csv
-    let mut exist = false;
-    for entry in fs::read_dir(dir_path).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_dir() {
-            continue;
-        }
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-        if file_name.starts_with("result_") {
-            exist = true;
-            break;
-        }
-    }
-    exist
+    !result_files(dir_path).is_empty()
 }
 
-// csv file format is like this:
-// date, LR, 62, 125, 250, 500, 1000, 1500, 2000, 3000, 4000, 6000, 8000, 10000, 12000
-// 20221128_205550, L, 24.0, 32.0, 10.0, 20.0, 30.0, 40.0, 45.0, 65.0, 23.0, 87.0, 13.0, 23.0, 56.0
-// 20221128_205550, R, 24.0, 32.0, 10.0, 20.0, 30.0, 40.0, 45.0, 65.0, 23.0, 87.0, 13.0, 23.0, 56.0
+/// The `result_NN.csv` files in `dir_path`, oldest first. Backups and
+/// leftover temp files are skipped.
+pub fn result_files(dir_path: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir_path)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().unwrap().is_file())
+        .map(|entry| entry.path())
+        .filter(|path| result_number(path).is_some())
+        .collect();
+    files.sort_by_key(|path| result_number(path));
+    files
+}
+
+/// NN of a `result_NN.csv` path.
+fn result_number(path: &Path) -> Option<u32> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("result_")?
+        .strip_suffix(".csv")?
+        .parse()
+        .ok()
+}
+
+/// Averages and degrees of loss of every session in a result directory,
+/// rewritten from the result files on every save.
+pub const SUMMARY_FILE: &str = "summary.csv";
+
+/// First line of a long-format result file.
+pub const SCHEMA_V2: &str = "# schema: v2";
+
+// Result files are written in the long format, one row per session, ear and
+// frequency:
+// # schema: v2
+// date,subject,transducer,ear,freq,level,scale,masked,masker_level,presentations,heard,no_response,no_plateau,device,calibration_profile,calibration_fingerprint,procedure,stimulus,memo,started,finished
+// 20230105_101500,S01,headphone,L,1000,-52,dbfs,true,-40,9,4,false,,,hughson_westlake,"{kind: warble, rate: 5.0, depth: 0.05}",,,
+//
+// Files saved before v2 are in the wide format, one row per session and ear:
+// date, LR[dBFS], 62.5, 125, 250, 500, 1000, 1500, 2000, 3000, 4000, 6000, 8000, 10000, 12000
+// 20221128_205550, headphone:L, 24.0, 32.0, 10.0, 20.0, 30.0, 40.0, 45.0, 65.0, 23.0, 87.0, 13.0, 23.0, 56.0
+// 20221128_205550, headphone:R, 24.0, 32.0, 10.0, 20.0, 30.0, 40.0, 45.0, 65.0, 23.0, 87.0, 13.0, 23.0, 56.0
 // 20221128_205550, L, 24.0, 32.0, 10.0, 20.0, 30.0, 40.0, 45.0, 65.0, 23.0, 87.0, 13.0, 23.0, 56.0
 // 20221128_205550, R, 24.0, 32.0, 10.0, 20.0, 30.0, 40.0, 45.0, 65.0, 23.0, 87.0, 13.0, 23.0, 56.0
-pub fn load_csv(dir_path: &str) -> BTreeMap<String, BTreeMap<String, BTreeMap<i32, f32>>> {
-    let mut result = BTreeMap::new();
-
+//
+// The wide format does not record the unit. A bare `LR` header is read as
+// dBFS, the only unit the first versions wrote; `LR[dBFS]` or `LR[dB HL]`
+// names it, e.g. for wide files holding calibrated sessions.
+pub fn load_csv(dir_path: &str) -> Result<Vec<Session>, String> {
     // at dir_path, there are result_01.csv or result_02.csv
     // load latest csv file
-    let path = fs::read_dir(dir_path)
-        .unwrap()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_type().unwrap().is_file())
-        .filter(|entry| entry.file_name().to_str().unwrap().contains("result_"))
-        .max_by_key(|entry| entry.file_name())
-        .unwrap()
-        .path();
+    match result_files(dir_path).last() {
+        Some(path) => read_csv(path),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Sessions of every result file in `dir_path`, oldest file first.
+pub fn load_all(dir_path: &str) -> Result<Vec<Session>, String> {
+    let mut sessions = Vec::new();
+    for path in result_files(dir_path) {
+        sessions.extend(read_csv(&path)?);
+    }
+    Ok(sessions)
+}
+
+/// One row of a v2 file.
+#[derive(Serialize, Deserialize)]
+struct Row {
+    date: String,
+    subject: Option<String>,
+    transducer: String,
+    ear: String,
+    freq: f32,
+    level: f32,
+    scale: Scale,
+    masked: bool,
+    masker_level: Option<f32>,
+    presentations: u32,
+    heard: u32,
+    /// Missing in files written before the column was added.
+    #[serde(default)]
+    no_response: bool,
+    /// Missing in files written before the column was added.
+    #[serde(default)]
+    no_plateau: bool,
+    device: Option<String>,
+    calibration_profile: Option<String>,
+    /// `Calibration::fingerprint`; missing in files written before the column
+    /// was added.
+    #[serde(default)]
+    calibration_fingerprint: Option<String>,
+    procedure: Option<Procedure>,
+    /// `StimulusKind` as a YAML flow mapping.
+    stimulus: Option<String>,
+    memo: String,
+    started: Option<String>,
+    finished: Option<String>,
+}
 
-    let mut reader = csv::Reader::from_path(path).unwrap();
-    let title_row = reader.headers().unwrap().to_owned();
+/// Reads every session of one result CSV file, in either format.
+pub fn read_csv(path: &Path) -> Result<Vec<Session>, String> {
+    let header = first_line(path);
+    if header == SCHEMA_V2 {
+        read_long(path)
+    } else if header.starts_with('#') {
+        Err(format!("unsupported {:?}", header))
+    } else {
+        read_wide(path)
+    }
+    .map_err(|message| format!("{}: {}", path.display(), message))
+}
+
+fn first_line(path: &Path) -> String {
+    let mut line = String::new();
+    BufReader::new(File::open(path).unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    line.trim_end().to_string()
+}
+
+fn read_long(path: &Path) -> Result<Vec<Session>, String> {
+    let mut result: Vec<Session> = Vec::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_path(path)
+        .map_err(|err| err.to_string())?;
+    for row in reader.deserialize() {
+        let row: Row = row.map_err(|err| err.to_string())?;
+        if result.last().map(|session| &session.date) != Some(&row.date) {
+            let mut session = Session::new(&row.date);
+            session.subject = row.subject;
+            session.memo = row.memo;
+            session.device = row.device;
+            session.calibration_profile = row.calibration_profile;
+            session.calibration_fingerprint = row.calibration_fingerprint;
+            session.procedure = row.procedure;
+            session.stimulus = match row.stimulus {
+                Some(stimulus) => Some(
+                    serde_yaml::from_str(&stimulus)
+                        .map_err(|err| format!("stimulus {:?}: {}", stimulus, err))?,
+                ),
+                None => None,
+            };
+            session.scale = row.scale;
+            session.started = row.started;
+            session.finished = row.finished;
+            result.push(session);
+        }
+        let session = result.last_mut().unwrap();
+        let ear = match session
+            .ears
+            .iter()
+            .position(|ear| ear.transducer == row.transducer && ear.ear == row.ear)
+        {
+            Some(i) => &mut session.ears[i],
+            None => {
+                session.ears.push(EarResult::new(&row.transducer, &row.ear));
+                session.ears.last_mut().unwrap()
+            }
+        };
+        ear.points.push(ThresholdPoint {
+            masker_level: row.masker_level,
+            presentations: row.presentations,
+            heard: row.heard,
+            no_response: row.no_response,
+            no_plateau: row.no_plateau,
+            ..ThresholdPoint::new(row.freq, row.level)
+        });
+    }
+    Ok(result)
+}
+
+fn read_wide(path: &Path) -> Result<Vec<Session>, String> {
+    let mut result: Vec<Session> = Vec::new();
+    let mut reader = csv::Reader::from_path(path).map_err(|err| err.to_string())?;
+    let title_row = reader.headers().map_err(|err| err.to_string())?.to_owned();
+    let scale = match title_row.get(1).map(str::trim) {
+        Some("LR") => Scale::Dbfs,
+        Some(lr) => [Scale::Dbfs, Scale::HearingLevel]
+            .into_iter()
+            .find(|scale| lr == format!("LR[{}]", scale.unit()))
+            .ok_or_else(|| format!("unknown unit {:?}, expected LR, LR[dBFS] or LR[dB HL]", lr))?,
+        None => return Err("no LR column".to_string()),
+    };
+    let number = |value: &str| {
+        value
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| format!("{:?} is not a number", value))
+    };
     for row in reader.records() {
-        let row = row.unwrap();
+        let row = row.map_err(|err| err.to_string())?;
         let date = row.get(0).unwrap().to_string();
-        let lr = row.get(1).unwrap().to_string();
-        let mut data = BTreeMap::new();
+        let (transducer, ear) = split_key(row.get(1).unwrap_or_default().trim());
+        let mut data = EarResult::new(transducer, ear);
         for (i, value) in row.iter().enumerate() {
             if i > 1 {
-                let freq = title_row.get(i).unwrap().parse::<i32>().unwrap();
-                let volume = value.parse::<f32>().unwrap();
-                data.insert(freq, volume);
+                let freq = title_row.get(i).ok_or("more levels than frequencies")?;
+                data.points
+                    .push(ThresholdPoint::new(number(freq)?, number(value)?));
             }
         }
-        result
-            .entry(date)
-            .or_insert(BTreeMap::new())
-            .insert(lr, data);
+        if result.last().map(|session| &session.date) != Some(&date) {
+            result.push(Session {
+                scale,
+                ..Session::new(&date)
+            });
+        }
+        result.last_mut().unwrap().ears.push(data);
     }
-    result
+    Ok(result)
 }
 
-pub fn save_to_csv(result: BTreeMap<String, BTreeMap<i32, f32>>, dir_path: &str, now_date: &str) {
-    // if dir_path is not exist, create dir
-    if !fs::metadata(dir_path).is_ok() {
-        fs::create_dir(dir_path).unwrap();
-    }
-
-    // load current csv file
+/// Adds `session` to the newest result file of `dir_path` and rewrites the
+/// summary. A result file that cannot be read is left as it is.
+pub fn save_to_csv(session: &Session, dir_path: &str) -> Result<(), String> {
+    // load current csv file, creating dir_path if it does not exist
     if check_exist_csv(dir_path) {
-        let mut csv = load_csv(dir_path);
-        let mut is_same = true;
-        let first_data = csv.values().next().unwrap().values().next().unwrap();
-        for (freq, _) in result.values().next().unwrap() {
-            if !first_data.contains_key(freq) {
-                is_same = false;
-                break;
-            }
-        }
-        for (freq, _) in first_data {
-            if !result.values().next().unwrap().contains_key(freq) {
-                is_same = false;
-                break;
-            }
-        }
-
-        if !is_same {
-            let mut max = 0;
-            for entry in fs::read_dir(dir_path)
-                .unwrap()
-                .filter_map(|entry| entry.ok())
-                .filter(|entry| entry.file_type().unwrap().is_file())
-                .filter(|entry| entry.file_name().to_str().unwrap().contains("result_"))
-            {
-                let file_name = entry.file_name().to_str().unwrap().to_string();
-                let num = file_name[7..file_name.len() - 4].parse::<i32>().unwrap();
-                if num > max {
-                    max = num;
-                }
-            }
-            let mut path = dir_path.to_string();
-            path.push_str("\\result_");
-            path.push_str(format!("{:02}", max + 1).as_str());
-            path.push_str(".csv");
-            let mut writer = csv::Writer::from_path(path).unwrap();
-            let mut culumn = vec!["date".to_string(), "LR".to_string()];
-            for freq in result.get("L").unwrap().keys() {
-                culumn.push(freq.to_string());
-            }
-            writer.write_record(&culumn).unwrap();
-            for (lr, data) in result.iter() {
-                let mut record = vec![now_date.to_string(), lr.to_string()];
-                for freq in data.keys() {
-                    record.push(data.get(freq).unwrap().to_string());
-                }
-                writer.write_record(&record).unwrap();
-            }
-            writer.flush().unwrap();
+        let latest = result_files(dir_path).pop().unwrap();
+        if first_line(&latest) == SCHEMA_V2 {
+            let mut csv = load_csv(dir_path)?;
+            csv.retain(|saved| saved.date != session.date);
+            csv.push(session.clone());
+            write_sessions(&latest, &csv);
         } else {
-            //write to csv file
-            // get latest csv file path
-            let path = fs::read_dir(dir_path)
-                .unwrap()
-                .filter_map(|entry| entry.ok())
-                .filter(|entry| entry.file_type().unwrap().is_file())
-                .filter(|entry| entry.file_name().to_str().unwrap().contains("result_"))
-                .max_by_key(|entry| entry.file_name())
-                .unwrap()
-                .path();
-
-            // write to csv file
-            csv.insert(now_date.to_string(), result);
-            let mut writer = csv::Writer::from_path(path).unwrap();
-            let mut culumn = vec!["date".to_string(), "LR".to_string()];
-            for freq in csv.values().next().unwrap().values().next().unwrap().keys() {
-                culumn.push(freq.to_string());
-            }
-            writer.write_record(&culumn).unwrap();
-            for (date, lr) in csv {
-                for (lr, data) in lr {
-                    let mut row = vec![date.to_string(), lr.to_string()];
-                    for freq in culumn.iter().skip(2) {
-                        row.push(data.get(&freq.parse::<i32>().unwrap()).unwrap().to_string());
-                    }
-                    writer.write_record(&row).unwrap();
-                }
-            }
-            writer.flush().unwrap();
+            // legacy wide files are left as they are
+            let num = result_number(&latest).unwrap() + 1;
+            let path = Path::new(dir_path).join(format!("result_{:02}.csv", num));
+            write_sessions(&path, std::slice::from_ref(session));
         }
     } else {
-        //write to csv file
-        let mut path = dir_path.to_string();
-        path.push_str("\\result_01.csv");
-        let mut writer = csv::Writer::from_path(path).unwrap();
-        let mut culumn = vec!["date".to_string(), "LR".to_string()];
-        for freq in result.get("L").unwrap().keys() {
-            culumn.push(freq.to_string());
+        let path = Path::new(dir_path).join("result_01.csv");
+        write_sessions(&path, std::slice::from_ref(session));
+    }
+    save_summary(dir_path)
+}
+
+/// One row of the summary, per session, transducer and ear.
+#[derive(Serialize, Deserialize)]
+struct SummaryRow {
+    date: String,
+    subject: Option<String>,
+    transducer: String,
+    ear: String,
+    scale: Scale,
+    pta4: Option<f32>,
+    hfa: Option<f32>,
+    lfa: Option<f32>,
+    who: Option<String>,
+    asha: Option<String>,
+}
+
+/// `value` rounded to 0.1 dB.
+fn tenth(value: f32) -> f32 {
+    (value * 10.0).round() / 10.0
+}
+
+/// Writes `SUMMARY_FILE` for the sessions of every result file in `dir_path`.
+fn save_summary(dir_path: &str) -> Result<(), String> {
+    let sessions = load_all(dir_path)?;
+    let path = Path::new(dir_path).join(SUMMARY_FILE);
+    let tmp_path = with_suffix(&path, ".tmp");
+    let mut writer = csv::Writer::from_path(&tmp_path).unwrap();
+    for session in sessions {
+        for summary in analysis::summarize(&session) {
+            let (transducer, ear) = split_key(&summary.key);
+            writer
+                .serialize(SummaryRow {
+                    date: session.date.clone(),
+                    subject: session.subject.clone(),
+                    transducer: transducer.to_string(),
+                    ear: ear.to_string(),
+                    scale: session.scale,
+                    pta4: summary.pta4.map(tenth),
+                    hfa: summary.hfa.map(tenth),
+                    lfa: summary.lfa.map(tenth),
+                    who: summary.who.map(str::to_string),
+                    asha: summary.asha.map(str::to_string),
+                })
+                .unwrap();
         }
-        writer.write_record(&culumn).unwrap();
-        for (lr, data) in result.iter() {
-            let mut record = vec![now_date.to_string(), lr.to_string()];
-            for freq in data.keys() {
-                record.push(data.get(freq).unwrap().to_string());
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    fs::rename(&tmp_path, &path).unwrap();
+    Ok(())
+}
+
+/// Writes `sessions` to `path` in the v2 format.
+///
+/// The file is written next to `path` first and renamed over it, so a crash
+/// leaves either the old or the new file, never a truncated one. The old file
+/// becomes the newest of the `BACKUPS`.
+fn write_sessions(path: &Path, sessions: &[Session]) {
+    let tmp_path = with_suffix(path, ".tmp");
+    let mut file = File::create(&tmp_path).unwrap();
+    writeln!(file, "{}", SCHEMA_V2).unwrap();
+    let mut writer = csv::Writer::from_writer(file);
+    for session in sessions {
+        let stimulus = session.stimulus.map(|stimulus| flow_yaml(&stimulus));
+        for ear in &session.ears {
+            for point in &ear.points {
+                writer
+                    .serialize(Row {
+                        date: session.date.clone(),
+                        subject: session.subject.clone(),
+                        transducer: ear.transducer.clone(),
+                        ear: ear.ear.clone(),
+                        freq: point.freq,
+                        level: point.level,
+                        scale: session.scale,
+                        masked: point.masker_level.is_some(),
+                        masker_level: point.masker_level,
+                        presentations: point.presentations,
+                        heard: point.heard,
+                        no_response: point.no_response,
+                        no_plateau: point.no_plateau,
+                        device: session.device.clone(),
+                        calibration_profile: session.calibration_profile.clone(),
+                        calibration_fingerprint: session.calibration_fingerprint.clone(),
+                        procedure: session.procedure,
+                        stimulus: stimulus.clone(),
+                        memo: session.memo.clone(),
+                        started: session.started.clone(),
+                        finished: session.finished.clone(),
+                    })
+                    .unwrap();
             }
-            writer.write_record(&record).unwrap();
         }
     }
+    let file = writer.into_inner().unwrap();
+    file.sync_all().unwrap();
+    drop(file);
+
+    if path.exists() {
+        rotate_backups(path);
+    }
+    fs::rename(&tmp_path, path).unwrap();
+}
+
+/// A flat struct or enum as single-line YAML, e.g.
+/// `{kind: warble, rate: 5.0, depth: 0.05}`.
+fn flow_yaml<T: Serialize>(value: &T) -> String {
+    let yaml = serde_yaml::to_string(value).unwrap();
+    format!("{{{}}}", yaml.lines().collect::<Vec<_>>().join(", "))
+}
+
+/// Shifts `path.bak.N` to `path.bak.N+1`, dropping the oldest, and copies
+/// `path` to `path.bak.1`.
+fn rotate_backups(path: &Path) {
+    for n in (1..BACKUPS).rev() {
+        let from = with_suffix(path, &format!(".bak.{}", n));
+        if from.exists() {
+            fs::rename(&from, with_suffix(path, &format!(".bak.{}", n + 1))).unwrap();
+        }
+    }
+    fs::copy(path, with_suffix(path, ".bak.1")).unwrap();
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::stimulus::StimulusKind;
+
+    /// A session with both ears at `freqs`, each level being freq / 1000.
+    fn session(date: &str, freqs: &[f32]) -> Session {
+        let mut session = Session::new(date);
+        for ear in ["L", "R"] {
+            let mut data = EarResult::new("headphone", ear);
+            for freq in freqs {
+                data.points.push(ThresholdPoint::new(*freq, -freq / 1000.0));
+            }
+            session.ears.push(data);
+        }
+        session
+    }
+
+    /// An empty directory under the system temp dir, unique to one test.
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("hearing-test-csv-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_str().unwrap().to_string()
+    }
 
     #[test]
     fn test_check_exist_csv() {
-        let dir_path = "./result";
-        let exist = check_exist_csv(dir_path);
-        assert_eq!(exist, false);
+        let dir_path = temp_dir("check_exist");
+        let exist = check_exist_csv(&dir_path);
+        assert!(!exist);
     }
 
     #[test]
     fn test_load_csv() {
-        let dir_path = "./result";
-        let result = load_csv(dir_path);
+        let dir_path = temp_dir("load");
+        let result = load_csv(&dir_path).unwrap();
         println!("{:?}", result);
         assert_eq!(result.len(), 0);
     }
 
     #[test]
     fn test_save_to_csv() {
-        let mut result = BTreeMap::new();
-        let mut data = BTreeMap::new();
-        data.insert(100, 0.1);
-        data.insert(200, 0.2);
-        data.insert(300, 0.3);
-        data.insert(400, 0.4);
-        data.insert(500, 0.5);
-        data.insert(600, 0.6);
-        data.insert(700, 0.7);
-        data.insert(800, 0.8);
-        data.insert(900, 0.9);
-        data.insert(1000, 1.0);
-        result.insert("L".to_string(), data);
-        let mut data = BTreeMap::new();
-        data.insert(100, 0.1);
-        data.insert(200, 0.2);
-        data.insert(300, 0.3);
-        data.insert(400, 0.4);
-        data.insert(500, 0.5);
-        data.insert(600, 0.6);
-        data.insert(700, 0.7);
-        data.insert(800, 0.8);
-        data.insert(900, 0.9);
-        data.insert(1000, 1.0);
-        result.insert("R".to_string(), data);
-        let dir_path = "./result";
-        let now_date = "20221128_205550";
-        save_to_csv(result, dir_path, now_date);
+        let freqs = [
+            100.0, 200.0, 300.0, 400.0, 500.0, 600.0, 700.0, 800.0, 900.0, 1000.0,
+        ];
+        let dir_path = temp_dir("save");
+        save_to_csv(&session("20221128_205550", &freqs), &dir_path).unwrap();
+        assert!(Path::new(&dir_path).join("result_01.csv").is_file());
+        assert_eq!(result_files(&dir_path).len(), 1);
     }
 
     #[test]
     fn test_all() {
-        let dir_path = "./result";
-
-        // delete all csv file, if exist
-        for entry in fs::read_dir(dir_path)
-            .unwrap()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.file_type().unwrap().is_file())
-            .filter(|entry| entry.file_name().to_str().unwrap().contains("result_"))
-        {
-            fs::remove_file(entry.path()).unwrap();
+        let dir_path = &temp_dir("all");
+
+        assert!(!check_exist_csv(dir_path));
+
+        let freqs = [
+            62.5, 200.0, 300.0, 400.0, 500.0, 600.0, 700.0, 800.0, 900.0, 1000.0,
+        ];
+        let result = session("20221128_205550", &freqs);
+        save_to_csv(&result, dir_path).unwrap();
+
+        assert!(check_exist_csv(dir_path));
+        let loaded = load_csv(dir_path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].ears, result.ears);
+
+        save_to_csv(&session("20221129_200000", &freqs), dir_path).unwrap();
+        assert_eq!(load_csv(dir_path).unwrap().len(), 2);
+
+        let freqs = [
+            62.5, 300.0, 400.0, 500.0, 600.0, 700.0, 800.0, 900.0, 1000.0,
+        ];
+        // the long format keeps sessions with other frequencies in the same file
+        save_to_csv(&session("20221130_200000", &freqs), dir_path).unwrap();
+        assert_eq!(load_csv(dir_path).unwrap().len(), 3);
+        assert_eq!(
+            load_csv(dir_path).unwrap()[2],
+            session("20221130_200000", &freqs)
+        );
+        assert_eq!(result_files(dir_path).len(), 1);
+    }
+
+    #[test]
+    fn test_long_format_round_trip() {
+        let dir_path = &temp_dir("long");
+        let mut result = session("20230105_101500", &[62.5, 1000.0]);
+        result.subject = Some("S01".to_string());
+        result.memo = "left, then right".to_string();
+        result.calibration_profile = Some("lab".to_string());
+        result.calibration_fingerprint = Some("5f0d6b2e1c4a9f83".to_string());
+        result.procedure = Some(Procedure::HughsonWestlake);
+        result.stimulus = Some(StimulusKind::Warble {
+            rate: 5.0,
+            depth: 0.05,
+        });
+        result.scale = Scale::HearingLevel;
+        result.ears[1].points[0].masker_level = Some(-40.0);
+        result.ears[1].points[0].presentations = 9;
+        result.ears[1].points[0].heard = 4;
+        result.ears[1].points[0].no_plateau = true;
+        result.ears[1].points[1].no_response = true;
+        save_to_csv(&result, dir_path).unwrap();
+
+        let path = Path::new(dir_path).join("result_01.csv");
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# schema: v2\ndate,subject,transducer,ear,freq,level,"));
+        // one row per ear and frequency
+        assert_eq!(text.lines().count(), 2 + 4);
+        assert!(text.contains("\"{kind: warble, rate: 5.0, depth: 0.05}\""));
+        assert_eq!(read_csv(&path).unwrap(), vec![result]);
+
+        // files written before the no_response and no_plateau columns still load
+        fs::write(
+            &path,
+            "# schema: v2\n\
+             date,subject,transducer,ear,freq,level,scale,masked,masker_level,presentations,\
+             heard,device,calibration_profile,procedure,stimulus,memo,started,finished\n\
+             20230105_101500,,headphone,L,1000,-52,dbfs,false,,9,4,,,,,,,\n",
+        )
+        .unwrap();
+        let point = &read_csv(&path).unwrap()[0].ears[0].points[0];
+        assert!(!point.no_response && !point.no_plateau);
+    }
+
+    #[test]
+    fn test_summary() {
+        let dir_path = &temp_dir("summary");
+        let mut result = session("20230105_101500", &[500.0, 1000.0, 2000.0, 4000.0]);
+        result.scale = Scale::HearingLevel;
+        for point in &mut result.ears[1].points {
+            point.level = point.freq / 100.0;
         }
+        save_to_csv(&result, dir_path).unwrap();
+
+        let summary = fs::read_to_string(Path::new(dir_path).join(SUMMARY_FILE)).unwrap();
+        assert_eq!(
+            summary,
+            "date,subject,transducer,ear,scale,pta4,hfa,lfa,who,asha\n\
+             20230105_101500,,headphone,L,hearing_level,-1.9,,,normal,normal\n\
+             20230105_101500,,headphone,R,hearing_level,18.8,,,normal,slight\n"
+        );
+    }
+
+    #[test]
+    fn test_legacy_wide_format() {
+        let dir_path = &temp_dir("wide");
+        let path = Path::new(dir_path).join("result_01.csv");
+        fs::write(
+            &path,
+            "date,LR,125,250\n20221128_205550,L,-30,-40\n20221128_205550,R,-31,-41\n",
+        )
+        .unwrap();
+        let loaded = read_csv(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].scale, Scale::Dbfs);
+        assert_eq!(loaded[0].ears[1].key(), "R");
+        assert_eq!(loaded[0].ears[1].points[1].level, -41.0);
+
+        // the unit can be named in the header
+        let named = Path::new(dir_path).join("named.csv");
+        fs::write(&named, "date,LR[dB HL],125\n20221128_205550,L,-5\n").unwrap();
+        assert_eq!(read_csv(&named).unwrap()[0].scale, Scale::HearingLevel);
+        fs::write(&named, "date,LR[dB SPL],125\n20221128_205550,L,-5\n").unwrap();
+        assert!(read_csv(&named)
+            .unwrap_err()
+            .contains("unknown unit \"LR[dB SPL]\""));
+        fs::write(&named, "date,LR,125\n20221128_205550,L,x\n").unwrap();
+        assert!(read_csv(&named)
+            .unwrap_err()
+            .ends_with("\"x\" is not a number"));
+        fs::remove_file(&named).unwrap();
+
+        // new sessions start a v2 file next to the legacy one
+        save_to_csv(&session("20230101_100000", &[125.0]), dir_path).unwrap();
+        let files = result_files(dir_path);
+        assert_eq!(files.len(), 2);
+        assert_eq!(first_line(&files[0]), "date,LR,125,250");
+        assert_eq!(first_line(&files[1]), SCHEMA_V2);
+        assert_eq!(load_csv(dir_path).unwrap().len(), 1);
+        assert_eq!(load_all(dir_path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_rotating_backups() {
+        let dir_path = &temp_dir("backups");
+        let path = Path::new(dir_path).join("result_01.csv");
+        let freqs = [125.0, 1000.0];
+        for day in 1..=5 {
+            save_to_csv(
+                &session(&format!("202301{:02}_100000", day), &freqs),
+                dir_path,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(read_csv(&path).unwrap().len(), 5);
+        // newest backup first, the oldest version is dropped
+        for n in 1..=BACKUPS {
+            let backup = with_suffix(&path, &format!(".bak.{}", n));
+            assert_eq!(read_csv(&backup).unwrap().len(), 5 - n);
+        }
+        assert!(!with_suffix(&path, ".bak.4").exists());
+        assert!(!with_suffix(&path, ".tmp").exists());
+        assert_eq!(result_files(dir_path), vec![path]);
+    }
+
+    #[test]
+    fn test_interrupted_write_keeps_results() {
+        let dir_path = &temp_dir("interrupted");
+        let path = Path::new(dir_path).join("result_01.csv");
+        let freqs = [125.0, 1000.0];
+        save_to_csv(&session("20230101_100000", &freqs), dir_path).unwrap();
+
+        // a crash while writing leaves a partial temp file next to the results
+        fs::write(with_suffix(&path, ".tmp"), "date,LR,125,1000\n2023").unwrap();
+        assert_eq!(load_csv(dir_path).unwrap().len(), 1);
 
-        assert_eq!(check_exist_csv(dir_path), false);
-
-        let now_date = "20221128_205550";
-        let mut result = BTreeMap::new();
-        let mut data = BTreeMap::new();
-        data.insert(100, 0.1);
-        data.insert(200, 0.2);
-        data.insert(300, 0.3);
-        data.insert(400, 0.4);
-        data.insert(500, 0.5);
-        data.insert(600, 0.6);
-        data.insert(700, 0.7);
-        data.insert(800, 0.8);
-        data.insert(900, 0.9);
-        data.insert(1000, 1.0);
-        result.insert("L".to_string(), data);
-        let mut data = BTreeMap::new();
-        data.insert(100, 0.1);
-        data.insert(200, 0.2);
-        data.insert(300, 0.3);
-        data.insert(400, 0.4);
-        data.insert(500, 0.5);
-        data.insert(600, 0.6);
-        data.insert(700, 0.7);
-        data.insert(800, 0.8);
-        data.insert(900, 0.9);
-        data.insert(1000, 1.0);
-        result.insert("R".to_string(), data);
-        save_to_csv(result.clone(), dir_path, now_date);
-
-        assert_eq!(check_exist_csv(dir_path), true);
-        assert_eq!(load_csv(dir_path).len(), 1);
-
-        save_to_csv(result.clone(), dir_path, "20221129_200000");
-        assert_eq!(load_csv(dir_path).len(), 2);
-
-        let mut result = BTreeMap::new();
-        let mut data = BTreeMap::new();
-        data.insert(100, 0.1);
-        data.insert(300, 0.3);
-        data.insert(400, 0.4);
-        data.insert(500, 0.5);
-        data.insert(600, 0.6);
-        data.insert(700, 0.7);
-        data.insert(800, 0.8);
-        data.insert(900, 0.9);
-        data.insert(1000, 1.0);
-        result.insert("L".to_string(), data);
-        let mut data = BTreeMap::new();
-        data.insert(100, 0.1);
-        data.insert(300, 0.3);
-        data.insert(400, 0.4);
-        data.insert(500, 0.5);
-        data.insert(600, 0.6);
-        data.insert(700, 0.7);
-        data.insert(800, 0.8);
-        data.insert(900, 0.9);
-        data.insert(1000, 1.0);
-        result.insert("R".to_string(), data);
-
-        save_to_csv(result.clone(), dir_path, "20221130_200000");
-        assert_eq!(load_csv(dir_path).len(), 1);
+        save_to_csv(&session("20230102_100000", &freqs), dir_path).unwrap();
+        assert_eq!(load_csv(dir_path).unwrap().len(), 2);
+        assert!(!with_suffix(&path, ".tmp").exists());
     }
 }
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::model::Session;
+use crate::Point;
+
+/// Version of the `SessionExport` layout.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// A self-contained session: the config it was run with and its results,
+/// including the calibration the levels were converted with.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SessionExport {
+    pub version: u32,
+    pub config: Point,
+    pub session: Session,
+}
+
+/// File format, chosen by extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    pub fn of(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Format::Json,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => panic!("{}: expected a .json, .yaml or .yml file", path.display()),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+        }
+    }
+}
+
+impl SessionExport {
+    pub fn new(config: &Point, session: &Session) -> SessionExport {
+        SessionExport {
+            version: EXPORT_VERSION,
+            config: config.clone(),
+            session: session.clone(),
+        }
+    }
+
+    /// Writes `<dir_path>/<date>_session.<json|yaml>`, next to the audiogram.
+    pub fn save_to_dir(&self, dir_path: &str, format: Format) -> PathBuf {
+        if fs::metadata(dir_path).is_err() {
+            fs::create_dir_all(dir_path).unwrap();
+        }
+        let path = Path::new(dir_path).join(format!(
+            "{}_session.{}",
+            self.session.date,
+            format.extension()
+        ));
+        self.save(&path);
+        path
+    }
+
+    pub fn save(&self, path: &Path) {
+        let text = match Format::of(path) {
+            Format::Json => serde_json::to_string_pretty(self).unwrap(),
+            Format::Yaml => serde_yaml::to_string(self).unwrap(),
+        };
+        fs::write(path, text).unwrap();
+    }
+
+    pub fn load(path: &Path) -> SessionExport {
+        let text = fs::read_to_string(path).unwrap();
+        let export: SessionExport = match Format::of(path) {
+            Format::Json => serde_json::from_str(&text).unwrap(),
+            Format::Yaml => serde_yaml::from_str(&text).unwrap(),
+        };
+        assert!(
+            export.version <= EXPORT_VERSION,
+            "{}: session export version {} is newer than this program",
+            path.display(),
+            export.version
+        );
+        export
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::{Calibration, Offsets, Retspl, Scale, StandardRetspl};
+    use crate::model::{EarResult, ThresholdPoint, Trial};
+    use crate::procedure::Response;
+    use std::collections::BTreeMap;
+
+    const CONFIG: &str = "
+memo: exported
+test_freq: [62.5, 1000, 4000]
+procedure: hughson_westlake
+stimulus: {kind: warble, rate: 4.0, depth: 0.1}
+channel_map:
+  - {transducer: headphone, channel: 0}
+  - {transducer: bone, channel: 2, channels: 1}
+masking:
+  effective_level: -60.0
+  criterion: 40.0
+  plateau: {max_level: -10.0}
+listener:
+  kind: simulated
+  audiogram:
+    L: {250: -70.0, 1000: -62.0}
+    bone:R: {1000: -50.0}
+calibration:
+  headphone: DT 770
+  retspl: {125: 30.0, 1000: 5.0}
+  offsets: {125: 90.0, 1000: 98.0}
+";
+
+    fn export() -> SessionExport {
+        let config: Point = serde_yaml::from_str(CONFIG).unwrap();
+        let mut session = Session::new("20230105_101500");
+        session.memo = config.memo.clone();
+        session.procedure = Some(config.procedure);
+        session.stimulus = Some(config.stimulus);
+        session.scale = Scale::HearingLevel;
+        session.calibration = Some(Calibration {
+            headphone: "TDH 39".to_string(),
+            transducer: "headphone".to_string(),
+            retspl: Retspl::Standard(StandardRetspl::Tdh39),
+            offsets: Offsets::Both(BTreeMap::from([(1000, 98.0)])),
+        });
+        for (transducer, ear) in [("headphone", "L"), ("bone", "R")] {
+            let mut result = EarResult::new(transducer, ear);
+            for freq in &config.test_freq {
+                result.points.push(ThresholdPoint {
+                    masker_level: Some(-35.0),
+                    presentations: 2,
+                    heard: 1,
+                    trials: vec![
+                        Trial {
+                            onset: 2.0,
+                            level: 22.5,
+                            masker_level: Some(-35.0),
+                            response: Response::NotHeard,
+                            reaction_time: 1.6,
+                        },
+                        Trial {
+                            onset: 4.4,
+                            level: 12.5,
+                            masker_level: Some(-35.0),
+                            response: Response::Heard,
+                            reaction_time: 0.5,
+                        },
+                    ],
+                    ..ThresholdPoint::new(*freq, 12.5)
+                });
+            }
+            session.ears.push(result);
+        }
+        SessionExport::new(&config, &session)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = std::env::temp_dir().join("hearing-test-export");
+        let _ = fs::remove_dir_all(&dir);
+        let export = export();
+        for format in [Format::Json, Format::Yaml] {
+            let path = export.save_to_dir(dir.to_str().unwrap(), format);
+            assert_eq!(
+                path.file_name().unwrap().to_str().unwrap(),
+                format!("20230105_101500_session.{}", format.extension())
+            );
+            assert_eq!(SessionExport::load(&path), export);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::output::SAMPLE_RATE;
+
+/// Presentation timing, the `timing` section of `config.yaml`. Durations in
+/// seconds.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Timing {
+    /// Tone on time of one burst, including the fades.
+    #[serde(default = "default_on")]
+    pub on: f32,
+    /// Silence between bursts.
+    #[serde(default = "default_off")]
+    pub off: f32,
+    /// Squared fade-in and fade-out at the ends of every burst.
+    #[serde(default = "default_fade")]
+    pub fade: f32,
+    /// Silence after a response, before the next presentation or frequency.
+    #[serde(default = "default_pause")]
+    pub pause: f32,
+}
+
+fn default_on() -> f32 {
+    0.4
+}
+
+fn default_off() -> f32 {
+    0.4
+}
+
+fn default_fade() -> f32 {
+    0.025
+}
+
+fn default_pause() -> f32 {
+    0.8
+}
+
+impl Default for Timing {
+    fn default() -> Timing {
+        Timing {
+            on: default_on(),
+            off: default_off(),
+            fade: default_fade(),
+            pause: default_pause(),
+        }
+    }
+}
+
+impl Timing {
+    /// Time a listener has to answer a presentation: two on/off cycles.
+    pub fn response_window(&self) -> Duration {
+        Duration::from_secs_f32(2.0 * (self.on + self.off))
+    }
+}
+
+/// On/off gating and channel routing shared by every stimulus source.
+///
+/// Sources are interleaved `channels`-channel 48kHz streams in which only
+/// `on_channel` carries signal. `Gate` walks through the interleaved samples and, for each
+/// sample of `on_channel`, hands out the frame index and the envelope gain.
+#[derive(Clone, Debug)]
+pub struct Gate {
+    volume_vec: Vec<f32>,
+    num_sample: usize,
+    on_channel: u16,
+    channels: u16,
+    current_channel: u16,
+}
+
+impl Gate {
+    /// Repeating on/off cycles of `timing`, see `envelope`.
+    pub fn new(timing: Timing, on_channel: u16, channels: u16) -> Gate {
+        Gate {
+            volume_vec: envelope(timing),
+            num_sample: 0,
+            on_channel,
+            channels,
+            current_channel: 0,
+        }
+    }
+
+    /// Always on, without fades.
+    pub fn continuous(on_channel: u16, channels: u16) -> Gate {
+        Gate {
+            volume_vec: vec![1.0],
+            num_sample: 0,
+            on_channel,
+            channels,
+            current_channel: 0,
+        }
+    }
+
+    /// Number of interleaved channels.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Advances by one interleaved sample.
+    /// Returns `Some((frame index, gain))` on `on_channel`, `None` on the other ones.
+    #[inline]
+    pub fn next(&mut self) -> Option<(usize, f32)> {
+        let channel = self.current_channel;
+        self.current_channel = (self.current_channel + 1) % self.channels;
+        if channel == self.on_channel {
+            self.num_sample = self.num_sample.wrapping_add(1);
+
+            let current_pos_per_loop = self.num_sample % self.volume_vec.len();
+            Some((self.num_sample, self.volume_vec[current_pos_per_loop]))
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds one on/off cycle of the gain envelope at `SAMPLE_RATE`: a `timing.fade`
+/// squared fade-in, `timing.on` of tone including the fades, a `timing.fade`
+/// fade-out and `timing.off` of silence.
+pub fn envelope(timing: Timing) -> Vec<f32> {
+    let mut volume_vec = Vec::new();
+
+    // build volume vector
+    let rate = SAMPLE_RATE as f32;
+    let fade_in_samples = (timing.fade * rate) as usize;
+    let fade_out_samples = (timing.fade * rate) as usize;
+    let on_samples = (timing.on * rate) as usize;
+    let off_samples = (timing.off * rate) as usize;
+
+    for i in 0..fade_in_samples {
+        volume_vec.push((i as f32 / fade_in_samples as f32).powf(2.0));
+    }
+    volume_vec.resize(on_samples - fade_out_samples, 1.0);
+    for i in 0..fade_out_samples {
+        volume_vec.push(1.0 - (i as f32 / fade_out_samples as f32).powf(2.0));
+    }
+    volume_vec.resize(volume_vec.len() + off_samples, 0.0);
+    volume_vec
+}
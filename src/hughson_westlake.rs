@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use crate::procedure::{Levels, Response, ResponseKind, Step, TestProcedure};
+
+/// Modified Hughson-Westlake ("down 10, up 5") threshold search.
+///
+/// Levels are in dBFS, the same unit as `db_to_float`/`float_to_db` in `main.rs`.
+/// After every heard tone the level drops by `step_down` (10 dB by default),
+/// after every missed tone it rises by `step_up` (5 dB). Only presentations
+/// that follow a miss belong to an ascending run, and the threshold is the
+/// lowest level heard on 2 of (at most) 3 ascending runs.
+#[derive(Clone, Debug)]
+pub struct HughsonWestlake {
+    start_level: f64,
+    level: f64,
+    min_level: f64,
+    max_level: f64,
+    step_down: f64,
+    step_up: f64,
+    ascending: bool,
+    // ascending presentations per level (in 0.1 dB): (presented, heard)
+    ascending_runs: BTreeMap<i64, (u32, u32)>,
+}
+
+impl HughsonWestlake {
+    /// Builds a new search that starts presenting at `levels.start` and never
+    /// leaves `levels.min..=levels.max`.
+    pub fn new(levels: &Levels) -> HughsonWestlake {
+        let start_level = levels.start.clamp(levels.min, levels.max);
+        HughsonWestlake {
+            start_level,
+            level: start_level,
+            min_level: levels.min,
+            max_level: levels.max,
+            step_down: levels.step_down,
+            step_up: levels.step_up,
+            ascending: false,
+            ascending_runs: BTreeMap::new(),
+        }
+    }
+}
+
+impl TestProcedure for HughsonWestlake {
+    fn response_kind(&self) -> ResponseKind {
+        ResponseKind::YesNo
+    }
+
+    fn start_level(&self) -> f64 {
+        self.start_level
+    }
+
+    fn respond(&mut self, response: Response) -> Step {
+        let heard = match response {
+            Response::Heard => true,
+            Response::NotHeard => false,
+            _ => return Step::Present(self.level),
+        };
+
+        if self.ascending {
+            let key = (self.level * 10.0).round() as i64;
+            let run = self.ascending_runs.entry(key).or_insert((0, 0));
+            run.0 += 1;
+            if heard {
+                run.1 += 1;
+            }
+            if run.1 >= 2 && run.0 <= 3 {
+                return Step::Threshold(self.level);
+            }
+        }
+
+        if heard {
+            if self.level <= self.min_level {
+                // heard at the softest allowed level, the threshold is at or below it
+                return Step::Threshold(self.min_level);
+            }
+            self.level = (self.level - self.step_down).max(self.min_level);
+            self.ascending = false;
+        } else {
+            if self.level >= self.max_level {
+                // no response at the loudest allowed level
+                return Step::NoResponse(self.max_level);
+            }
+            self.level = (self.level + self.step_up).min(self.max_level);
+            self.ascending = true;
+        }
+        Step::Present(self.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ideal listener that hears everything at or above `threshold`
+    fn run(threshold: f64, start: f64) -> Step {
+        let mut procedure = HughsonWestlake::new(&Levels {
+            start,
+            ..Levels::default()
+        });
+        let mut level = procedure.start_level();
+        for _ in 0..100 {
+            let response = if level >= threshold {
+                Response::Heard
+            } else {
+                Response::NotHeard
+            };
+            match procedure.respond(response) {
+                Step::Present(next) => level = next,
+                step => return step,
+            }
+        }
+        panic!("procedure did not converge");
+    }
+
+    #[test]
+    fn test_finds_threshold_on_grid() {
+        assert_eq!(run(-54.0, -24.0), Step::Threshold(-54.0));
+    }
+
+    #[test]
+    fn test_finds_first_level_above_threshold() {
+        assert_eq!(run(-56.0, -24.0), Step::Threshold(-54.0));
+    }
+
+    #[test]
+    fn test_no_response_returns_max_level() {
+        assert_eq!(run(10.0, -24.0), Step::NoResponse(0.0));
+    }
+
+    #[test]
+    fn test_heard_at_min_level_returns_min_level() {
+        assert_eq!(run(-200.0, -24.0), Step::Threshold(-120.0));
+    }
+
+    #[test]
+    fn test_threshold_needs_two_ascending_responses() {
+        let mut procedure = HughsonWestlake::new(&Levels::default());
+        // heard -24 -> -34, missed -34 -> -29 (ascending)
+        assert_eq!(procedure.respond(Response::Heard), Step::Present(-34.0));
+        assert_eq!(procedure.respond(Response::NotHeard), Step::Present(-29.0));
+        // first ascending response at -29 is not enough
+        assert_eq!(procedure.respond(Response::Heard), Step::Present(-39.0));
+        assert_eq!(procedure.respond(Response::NotHeard), Step::Present(-34.0));
+        assert_eq!(procedure.respond(Response::NotHeard), Step::Present(-29.0));
+        assert_eq!(procedure.respond(Response::Heard), Step::Threshold(-29.0));
+    }
+}
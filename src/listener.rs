@@ -0,0 +1,234 @@
+use crossterm::queue;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{stdout, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::{
+    cursor,
+    event::{poll, read, Event, KeyCode},
+    style::{self, Stylize},
+    terminal,
+};
+
+use crate::calibration::deserialize_tables;
+use crate::gate::Timing;
+use crate::procedure::{Response, ResponseKind};
+use crate::simulated::SimulatedListener;
+use crate::stimulus::Stimulus;
+
+/// A stimulus as it reaches the listener.
+#[derive(Clone, Copy, Debug)]
+pub struct Presentation<'a> {
+    pub transducer: &'a str,
+    pub ear: &'a str,
+    pub stimulus: &'a Stimulus,
+    /// Level in dBFS.
+    pub level: f64,
+}
+
+/// Whoever answers the stimuli: a person at the keyboard or a simulation.
+pub trait Listener {
+    /// Called before the first presentation of each frequency.
+    fn begin(&mut self, _kind: ResponseKind, _transducer: &str, _ear: &str, _stimulus: &Stimulus) {}
+
+    /// Waits for the answer to `presentation`.
+    /// Returns the response and the time it took, measured from stimulus onset.
+    fn respond(&mut self, kind: ResponseKind, presentation: &Presentation) -> (Response, Duration);
+
+    /// Called once the threshold of a frequency is known.
+    fn finish(&mut self, _transducer: &str, _ear: &str, _stimulus: &Stimulus, _threshold: f64) {}
+
+    /// Silent gap between presentations.
+    fn pause(&mut self, _duration: Duration) {}
+}
+
+/// Listener selected in `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum ListenerConfig {
+    /// A person answering with the keyboard.
+    #[default]
+    Keyboard,
+    /// A virtual participant with a known audiogram.
+    Simulated {
+        /// Thresholds in dBFS per ear and frequency. Keys are "L"/"R", or
+        /// e.g. "bone:L" for a transducer that should hear differently.
+        #[serde(deserialize_with = "deserialize_tables")]
+        audiogram: BTreeMap<String, BTreeMap<i32, f64>>,
+        /// Slope of the psychometric function in 1/dB.
+        #[serde(default = "default_slope")]
+        slope: f64,
+        /// Probability of answering "heard" regardless of the level.
+        #[serde(default)]
+        false_positive_rate: f64,
+        /// Cross-hearing: how much softer the other ear hears the tone, in dB.
+        #[serde(default)]
+        interaural_attenuation: Option<f64>,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+fn default_slope() -> f64 {
+    1.0
+}
+
+impl ListenerConfig {
+    /// The listener, answering within the response window of `timing`.
+    pub fn open(&self, timing: Timing) -> Box<dyn Listener> {
+        match self {
+            ListenerConfig::Keyboard => Box::new(KeyboardListener::new(timing)),
+            ListenerConfig::Simulated {
+                audiogram,
+                slope,
+                false_positive_rate,
+                interaural_attenuation,
+                seed,
+            } => Box::new(SimulatedListener::new(
+                audiogram.clone(),
+                *slope,
+                *false_positive_rate,
+                *interaural_attenuation,
+                timing,
+                *seed,
+            )),
+        }
+    }
+
+    /// Whether a person is in front of the terminal.
+    pub fn is_interactive(&self) -> bool {
+        matches!(self, ListenerConfig::Keyboard)
+    }
+}
+
+/// Reads responses from the terminal with crossterm.
+pub struct KeyboardListener {
+    stdout: Stdout,
+    /// How long a yes/no answer is waited for.
+    window: Duration,
+}
+
+impl KeyboardListener {
+    pub fn new(timing: Timing) -> KeyboardListener {
+        KeyboardListener {
+            stdout: stdout(),
+            window: timing.response_window(),
+        }
+    }
+}
+
+impl Listener for KeyboardListener {
+    fn begin(&mut self, kind: ResponseKind, transducer: &str, ear: &str, stimulus: &Stimulus) {
+        let instruction = match kind {
+            ResponseKind::Adjust => "Press the up/down keys to adjust the volume and press Enter the first time you hear nothing.",
+            ResponseKind::YesNo => "Press Space as soon as you hear the tone.",
+        };
+        queue!(
+            self.stdout,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            cursor::MoveToNextLine(1),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            cursor::MoveTo(0, 0),
+            style::PrintStyledContent(instruction.bold()),
+            cursor::MoveToNextLine(1),
+            style::PrintStyledContent("Current frequency: ".green()),
+            style::Print(format!("{:?} Hz", stimulus.freq)),
+            style::PrintStyledContent("   LR: ".green()),
+            style::Print(format!("{:?}", ear)),
+            style::PrintStyledContent("   Transducer: ".green()),
+            style::Print(transducer),
+            cursor::MoveToNextLine(1),
+        )
+        .unwrap();
+        if kind == ResponseKind::Adjust {
+            queue!(
+                self.stdout,
+                style::Print("If audio signal channel is swapped, press 'r' to reset.".to_string()),
+                cursor::MoveToNextLine(1),
+            )
+            .unwrap();
+        }
+        self.stdout.flush().unwrap();
+    }
+
+    fn respond(
+        &mut self,
+        kind: ResponseKind,
+        _presentation: &Presentation,
+    ) -> (Response, Duration) {
+        let onset = Instant::now();
+        let response = match kind {
+            ResponseKind::Adjust => read_adjust_response(),
+            ResponseKind::YesNo => {
+                if wait_for_key(KeyCode::Char(' '), self.window) {
+                    Response::Heard
+                } else {
+                    Response::NotHeard
+                }
+            }
+        };
+        (response, onset.elapsed())
+    }
+
+    fn finish(&mut self, _transducer: &str, _ear: &str, stimulus: &Stimulus, threshold: f64) {
+        // print volume
+        queue!(
+            self.stdout,
+            cursor::MoveToNextLine(1),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::PrintStyledContent(
+                format!("Result of {:?} Hz -> Volume: ", stimulus.freq as i32).green()
+            ),
+            style::Print(format!("{:.3} dB", threshold)),
+        )
+        .unwrap();
+        self.stdout.flush().unwrap();
+    }
+
+    fn pause(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+        drain_key_events();
+    }
+}
+
+/// Blocks until an adjustment key is pressed.
+/// Up/Down move the volume by one step, 'r' restarts the tone (e.g. when the
+/// audio channel is swapped) and Enter accepts the current volume.
+fn read_adjust_response() -> Response {
+    loop {
+        if let Ok(Event::Key(event)) = read() {
+            match event.code {
+                KeyCode::Up => return Response::Up,
+                KeyCode::Down => return Response::Down,
+                KeyCode::Char('r') => return Response::Reset,
+                KeyCode::Enter => return Response::Confirm,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Waits up to `window` for `code` to be pressed.
+fn wait_for_key(code: KeyCode, window: Duration) -> bool {
+    let start = Instant::now();
+    while let Some(remaining) = window.checked_sub(start.elapsed()) {
+        if !poll(remaining).unwrap() {
+            break;
+        }
+        if let Event::Key(key) = read().unwrap() {
+            if key.code == code {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Discards key presses made between presentations.
+fn drain_key_events() {
+    while poll(Duration::ZERO).unwrap() {
+        read().unwrap();
+    }
+}
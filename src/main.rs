@@ -1,38 +1,214 @@
-use crossterm::{execute, queue};
-use rodio::source::Source;
-use rodio::{Decoder, OutputStream, Sink};
+use clap::Parser;
+use crossterm::queue;
 use serde::{Deserialize, Serialize};
-use serde_yaml::Mapping;
-use std::collections::BTreeMap;
-use std::collections::HashMap;
-use std::fs::File;
-use std::path::Path;
-use std::time::Duration;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use crossterm::{
     cursor,
     event::{read, Event},
     style::{self, Stylize},
-    terminal, ExecutableCommand, QueueableCommand, Result,
+    terminal,
 };
-use std::io::{stdout, BufRead, BufReader, Write};
+use std::io::{stdout, Stdout, Write};
 
+use analysis::FindingCriteria;
+use calibration::{Calibration, CalibrationProfile};
+use channel_map::{channel_count, EarOrder, Transducer};
+use cli::{Cli, Command, RunArgs};
+use export::{Format, SessionExport};
+use gate::Timing;
+use listener::ListenerConfig;
+use masking::MaskingConfig;
+use model::Session;
+use output::OutputConfig;
+use plot::PlotConfig;
+use procedure::{Levels, Procedure};
+use stimulus::StimulusKind;
+use store::Store;
+use subject::Subject;
+use validate::Severity;
+
+mod analysis;
+mod calibrate;
+mod calibration;
+mod channel_map;
+mod cli;
 mod csv;
+mod export;
+mod gate;
+mod hughson_westlake;
+mod listener;
+mod masking;
+mod model;
+mod noise;
+mod output;
+mod pdf;
 mod plot;
+mod procedure;
+mod session;
+mod simulated;
 mod sinewave;
+mod stimulus;
+mod store;
+mod subject;
+mod trend;
+mod validate;
+mod warble;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 struct Point {
     memo: String,
     test_freq: Vec<f32>,
+    #[serde(default)]
+    procedure: Procedure,
+    #[serde(default)]
+    stimulus: StimulusKind,
+    #[serde(default)]
+    output: OutputConfig,
+    #[serde(default)]
+    listener: ListenerConfig,
+    /// Start level and step sizes of the procedure.
+    #[serde(default)]
+    levels: Levels,
+    /// Tone on/off times, fades and the pause after a response.
+    #[serde(default)]
+    timing: Timing,
+    /// Ears in test order.
+    #[serde(default = "channel_map::default_ears")]
+    ears: Vec<String>,
+    /// Test `ears` in the listed or a random order.
+    #[serde(default)]
+    ear_order: EarOrder,
+    /// Transducers and the output channels they are wired to.
+    #[serde(default = "channel_map::default_channel_map")]
+    channel_map: Vec<Transducer>,
+    /// Audiogram style.
+    #[serde(default)]
+    plot: PlotConfig,
+    /// Criteria of the noise notch and asymmetry findings.
+    #[serde(default)]
+    findings: FindingCriteria,
+    /// Contralateral masking of the non-test ear.
+    #[serde(default)]
+    masking: Option<MaskingConfig>,
+    /// Converts results to dB HL when present.
+    #[serde(default)]
+    calibration: Option<Calibration>,
+    /// Name of a profile saved by `hearing-test calibrate --profile <name>`; replaces `calibration`.
+    #[serde(default)]
+    calibration_profile: Option<String>,
+    /// Refuse to start when the calibration profile is older than this.
+    #[serde(default)]
+    calibration_max_age_days: Option<f64>,
 }
 
 fn main() {
-    // init
+    let cli = Cli::parse();
+    match &cli.command {
+        None => run(&cli, &cli.run),
+        Some(Command::Run(args)) => run(&cli, args),
+        Some(Command::Calibrate { profile }) => {
+            let (config, source) = load_yaml_config(&cli.config);
+            validate_or_exit(&cli.config, &config, Some(&source));
+            calibrate::run(&config, profile, &cli.calibration_dir)
+                .unwrap_or_else(|message| fail(&message));
+        }
+        Some(Command::Plot { session }) => {
+            let session = find_session(&cli, session).unwrap_or_else(|message| fail(&message));
+            let dir_path = subject::result_dir(&cli.output_dir, session.subject.as_deref());
+            let (plot, criteria) = report_config(&cli);
+            let findings = analysis::findings(&session, &criteria);
+            plot::plot_audiogram(&session, &dir_path, &plot, &findings);
+            plot::plot_staircase(&session, &dir_path);
+        }
+        Some(Command::History { subject, plot }) => {
+            history(&cli, subject.as_deref());
+            if *plot {
+                history_plot(&cli, subject.as_deref());
+            }
+        }
+        Some(Command::Export { session, output }) => {
+            let export = find_export(&cli, session).unwrap_or_else(|message| fail(&message));
+            export.save(output);
+        }
+        Some(Command::Import { session }) => {
+            let export = SessionExport::load(session);
+            let (plot, criteria) = report_config(&cli);
+            save_results(&export.session, &cli.output_dir, &plot, &criteria);
+            println!("imported session {}", export.session.date);
+        }
+        Some(Command::ImportCsv { dir, subject }) => {
+            let dir_path = dir.as_deref().unwrap_or(&cli.output_dir);
+            let mut store = open_store(&cli.output_dir);
+            let imported = store
+                .import_csv_dir(dir_path, subject.as_deref())
+                .unwrap_or_else(|message| fail(&message));
+            println!(
+                "imported {} sessions, {} in {} (schema v{})",
+                imported,
+                store.sessions(None).unwrap().len(),
+                store_path(&cli.output_dir).display(),
+                store.schema_version().unwrap()
+            );
+        }
+        Some(Command::Subjects) => {
+            let store = open_store(&cli.output_dir);
+            for subject in store.subjects().unwrap() {
+                let sessions = store.sessions(Some(&subject.id)).unwrap().len();
+                println!(
+                    "{}\t{}\t{}\t{} sessions\t{}",
+                    subject.id,
+                    subject.name,
+                    subject
+                        .birth_year
+                        .map(|year| year.to_string())
+                        .unwrap_or_default(),
+                    sessions,
+                    subject.notes
+                );
+            }
+        }
+        Some(Command::ValidateConfig) => {
+            let (mut config, source) = load_yaml_config(&cli.config);
+            load_calibration_profile(&cli, &mut config);
+            let warnings = validate_or_exit(&cli.config, &config, Some(&source));
+            println!("{}: ok, {} warnings", cli.config.display(), warnings);
+        }
+    }
+}
+
+/// Runs the test with the config file and the overrides of `args`.
+fn run(cli: &Cli, args: &RunArgs) {
     let mut stdout = stdout();
-    let config = load_yaml_config(Path::new("config.yaml"));
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    let mut result: BTreeMap<String, BTreeMap<i32, f32>> = BTreeMap::new();
+    let (mut config, source) = load_yaml_config(&cli.config);
+    if let Some(ear) = &args.ear {
+        config.ears = vec![ear.clone()];
+    }
+    if let Some(freqs) = &args.freqs {
+        config.test_freq = freqs.clone();
+    }
+    if let Some(profile) = &args.profile {
+        config.calibration_profile = Some(profile.clone());
+    }
+    let device = load_calibration_profile(cli, &mut config);
+    config.plot = plot_options(cli, config.plot);
+    // overridden values are not in the file
+    let overridden = args.ear.is_some() || args.freqs.is_some();
+    validate_or_exit(
+        &cli.config,
+        &config,
+        Some(source.as_str()).filter(|_| !overridden),
+    );
+    let subject = pick_subject(&cli.output_dir, args).unwrap_or_else(|message| fail(&message));
+
+    let now_date = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let mut output = config
+        .output
+        .open(&now_date, channel_count(&config.channel_map))
+        .unwrap_or_else(|message| fail(&message));
+    let mut listener = config.listener.open(config.timing);
 
     //-------------------
     // let sink = Sink::try_new(&stream_handle).unwrap();
@@ -42,12 +218,302 @@ fn main() {
     // sink.append(source);
     //-------------------
 
+    if config.listener.is_interactive() {
+        welcome(&mut stdout, &config, subject.as_ref());
+    }
+
+    let mut session = Session::new(&now_date);
+    session.subject = subject.map(|subject| subject.id);
+    session.memo = config.memo.clone();
+    session.device = device;
+    session.calibration_profile = config.calibration_profile.clone();
+    session.procedure = Some(config.procedure);
+    session.stimulus = Some(config.stimulus);
+    session.started = Some(chrono::Local::now().to_rfc3339());
+    session.ears = session::run_session(&config, output.as_mut(), listener.as_mut());
+    session.finished = Some(chrono::Local::now().to_rfc3339());
+    if let Some(calibration) = &config.calibration {
+        match calibration.to_hearing_level(&session) {
+            Ok(converted) => session = converted,
+            Err(message) => eprintln!("{}; the results are kept in dBFS", message),
+        }
+    }
+
+    // print result
+    queue!(
+        stdout,
+        cursor::MoveToNextLine(1),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        style::PrintStyledContent(format!("Result [{}]: ", session.scale.unit()).green()),
+    )
+    .unwrap();
+    for ear in &session.ears {
+        let points: Vec<String> = ear
+            .points
+            .iter()
+            .map(|point| {
+                let level = if point.no_response {
+                    format!("no response at {:.1}", point.level)
+                } else {
+                    format!("{:.1}", point.level)
+                };
+                match point.masker_level {
+                    Some(masker) if point.no_plateau => format!(
+                        "{} Hz: {} (masked, {} dBFS, no plateau)",
+                        point.freq, level, masker
+                    ),
+                    Some(masker) => {
+                        format!("{} Hz: {} (masked, {} dBFS)", point.freq, level, masker)
+                    }
+                    None => format!("{} Hz: {}", point.freq, level),
+                }
+            })
+            .collect();
+        queue!(
+            stdout,
+            cursor::MoveToNextLine(1),
+            style::PrintStyledContent(format!("{}: ", ear.key()).green()),
+            style::Print(points.join(", ")),
+        )
+        .unwrap();
+    }
+    for summary in analysis::summarize(&session) {
+        queue!(
+            stdout,
+            cursor::MoveToNextLine(1),
+            style::Print(summary.describe(session.scale)),
+        )
+        .unwrap();
+    }
+    for finding in analysis::findings(&session, &config.findings) {
+        queue!(
+            stdout,
+            cursor::MoveToNextLine(1),
+            style::PrintStyledContent(finding.describe().yellow()),
+            cursor::MoveToNextLine(1),
+            style::Print(format!("  rule: {}", finding.rule)),
+        )
+        .unwrap();
+    }
+    stdout.flush().unwrap();
+
+    // finish rendering before the results are written
+    drop(output);
+
+    let dir_path = save_results(&session, &cli.output_dir, &config.plot, &config.findings);
+    SessionExport::new(&config, &session).save_to_dir(&dir_path, Format::Json);
+}
+
+/// Replaces the calibration of `config` with its `calibration_profile`, if it
+/// names one. Returns the device the profile was measured on.
+fn load_calibration_profile(cli: &Cli, config: &mut Point) -> Option<String> {
+    let name = config.calibration_profile.as_ref()?;
+    match CalibrationProfile::load(&cli.calibration_dir, name, config.calibration_max_age_days) {
+        Ok(profile) => {
+            config.calibration = Some(profile.calibration);
+            Some(profile.device)
+        }
+        Err(message) => fail(&message),
+    }
+}
+
+/// The `plot` and `findings` sections of the config file, or the defaults
+/// without one, for commands that plot sessions they did not run.
+fn report_config(cli: &Cli) -> (PlotConfig, FindingCriteria) {
+    if !cli.config.exists() {
+        return (
+            plot_options(cli, PlotConfig::default()),
+            FindingCriteria::default(),
+        );
+    }
+    let (config, source) = load_yaml_config(&cli.config);
+    validate_or_exit(&cli.config, &config, Some(&source));
+    (plot_options(cli, config.plot), config.findings)
+}
+
+/// `plot` with `--plot-format` and `--plot-size` applied.
+fn plot_options(cli: &Cli, mut plot: PlotConfig) -> PlotConfig {
+    if let Some(format) = cli.plot_format {
+        plot.format = format;
+    }
+    if cli.plot_size.is_some() {
+        plot.size = cli.plot_size;
+    }
+    plot
+}
+
+/// Prints `message` and exits with a failure status.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn store_path(output_dir: &str) -> PathBuf {
+    Path::new(output_dir).join(store::FILE_NAME)
+}
+
+fn open_store(output_dir: &str) -> Store {
+    Store::open(&store_path(output_dir)).unwrap()
+}
+
+/// The subject given with `--subject <ID>`, registered on first use. `--name`,
+/// `--birth-year` and `--notes` fill in or update the registry entry.
+fn pick_subject(output_dir: &str, args: &RunArgs) -> Result<Option<Subject>, String> {
+    let Some(id) = &args.subject else {
+        return Ok(None);
+    };
+    let mut store = open_store(output_dir);
+    let mut subject = match store.subject(id).unwrap() {
+        Some(subject) => subject,
+        None => Subject::new(id)?,
+    };
+    if let Some(name) = &args.name {
+        subject.name = name.clone();
+    }
+    if let Some(year) = args.birth_year {
+        subject.birth_year = Some(year);
+    }
+    if let Some(notes) = &args.notes {
+        subject.notes = notes.clone();
+    }
+    store.save_subject(&subject).unwrap();
+    Ok(Some(subject))
+}
+
+/// A session export given as a file, or the export saved with the stored
+/// session of that date.
+fn find_export(cli: &Cli, session: &str) -> Result<SessionExport, String> {
+    if Path::new(session).is_file() {
+        return Ok(SessionExport::load(Path::new(session)));
+    }
+    let stored = stored_session(cli, session)?;
+    let dir_path = subject::result_dir(&cli.output_dir, stored.subject.as_deref());
+    let path = Path::new(&dir_path).join(format!("{}_session.json", stored.date));
+    if !path.is_file() {
+        return Err(format!("{}: no session export", path.display()));
+    }
+    Ok(SessionExport::load(&path))
+}
+
+/// A session from an export file, or the stored session of that date.
+fn find_session(cli: &Cli, session: &str) -> Result<Session, String> {
+    if Path::new(session).is_file() {
+        return Ok(SessionExport::load(Path::new(session)).session);
+    }
+    stored_session(cli, session)
+}
+
+fn stored_session(cli: &Cli, date: &str) -> Result<Session, String> {
+    let mut sessions: Vec<Session> = open_store(&cli.output_dir)
+        .sessions(None)
+        .unwrap()
+        .into_iter()
+        .filter(|session| session.date == date)
+        .collect();
+    match sessions.len() {
+        0 => Err(format!("{}: no such file or stored session", date)),
+        1 => Ok(sessions.remove(0)),
+        _ => Err(format!(
+            "{}: stored for several subjects, give the session export file instead",
+            date
+        )),
+    }
+}
+
+/// Lists the stored sessions, oldest first.
+fn history(cli: &Cli, subject: Option<&str>) {
+    for session in open_store(&cli.output_dir).sessions(subject).unwrap() {
+        let ears: Vec<String> = session.ears.iter().map(|ear| ear.key()).collect();
+        let freqs = session
+            .ears
+            .first()
+            .map(|ear| ear.points.len())
+            .unwrap_or(0);
+        println!(
+            "{}\t{}\t{}\t{}\t{} frequencies\t{}",
+            session.date,
+            session.subject.as_deref().unwrap_or("-"),
+            session.scale.unit(),
+            ears.join(","),
+            freqs,
+            session.memo
+        );
+    }
+}
+
+/// Draws the history of the sessions in the result directory of `subject` and
+/// lists the thresholds that shifted significantly from their baseline.
+fn history_plot(cli: &Cli, subject: Option<&str>) {
+    let config = report_config(cli).0.history;
+    let dir_path = subject::result_dir(&cli.output_dir, subject);
+    let sessions =
+        trend::comparable(csv::load_all(&dir_path).unwrap_or_else(|message| fail(&message)));
+    if sessions.len() < 2 {
+        println!("{}: fewer than two sessions to compare", dir_path);
+        return;
+    }
+    plot::plot_history(sessions.clone(), &dir_path, &config);
+    let unit = sessions.last().unwrap().scale.unit();
+    let shifts = trend::significant_shifts(&sessions, config.shift);
+    println!(
+        "{} significant shifts of {} dB or more",
+        shifts.len(),
+        config.shift
+    );
+    for shift in shifts {
+        println!(
+            "{}\t{}\t{} Hz\t{:+.1} {} since {}",
+            shift.date, shift.key, shift.freq, shift.change, unit, shift.baseline
+        );
+    }
+}
+
+/// Adds `session` to the CSV results of its subject under `base_dir` and to the
+/// history database, and plots it with the findings that meet `criteria`.
+/// Returns the subject's result directory.
+fn save_results(
+    session: &Session,
+    base_dir: &str,
+    plot: &PlotConfig,
+    criteria: &FindingCriteria,
+) -> String {
+    let dir_path = subject::result_dir(base_dir, session.subject.as_deref());
+    // the session is still stored and plotted when an old result file is unreadable
+    if let Err(message) = csv::save_to_csv(session, &dir_path) {
+        eprintln!("{}", message);
+    }
+    open_store(base_dir).insert_session(session).unwrap();
+    // plot
+    let findings = analysis::findings(session, criteria);
+    plot::plot_audiogram(session, &dir_path, plot, &findings);
+    plot::plot_staircase(session, &dir_path);
+    if let Ok(sessions) = csv::load_all(&dir_path) {
+        plot::plot_history(sessions, &dir_path, &plot.history);
+    }
+    dir_path
+}
+
+/// Shows the config and waits for Enter before the first tone.
+fn welcome(stdout: &mut Stdout, config: &Point, subject: Option<&Subject>) {
     queue!(
         stdout,
         terminal::Clear(terminal::ClearType::All),
         cursor::MoveTo(0, 0),
         style::PrintStyledContent("Welcome to the sound test!".bold()),
         cursor::MoveToNextLine(1),
+    )
+    .unwrap();
+    if let Some(subject) = subject {
+        queue!(
+            stdout,
+            style::PrintStyledContent("Subject: ".green()),
+            style::Print(format!("{} {}", subject.id, subject.name)),
+            cursor::MoveToNextLine(1),
+        )
+        .unwrap();
+    }
+    queue!(
+        stdout,
         style::PrintStyledContent("Your config memo is: ".green()),
         style::Print(format!("{:?}", config.memo)),
         cursor::MoveToNextLine(1),
@@ -59,8 +525,12 @@ fn main() {
         style::PrintStyledContent("IMPORTANT:".bold().white()),
         style::SetBackgroundColor(style::Color::Reset),
         style::SetForegroundColor(style::Color::Reset),
-        style::Print(" We will play a sin wave with a peak of ".to_string()),
-        style::PrintStyledContent("-24dBFS(max. -19.7LUFS)".bold()),
+        style::Print(format!(
+            " We will play {}, first at ",
+            config.stimulus.describe()
+        )),
+        style::PrintStyledContent(format!("{} dBFS", config.levels.start).bold()),
+        style::Print(format!(" and at most {} dBFS", config.levels.max)),
         style::Print(". Make sure that the volume is not too loud.".to_string()),
         cursor::MoveToNextLine(2),
         style::Print("Press Enter key to start the test.".to_string()),
@@ -77,142 +547,36 @@ fn main() {
 
     queue!(stdout, terminal::Clear(terminal::ClearType::All),).unwrap();
     stdout.flush().unwrap();
-
-    // play
-    // iterate over the test_freq
-    for channel in 0..2 {
-        result.insert(
-            if channel == 0 {
-                "L".to_string()
-            } else {
-                "R".to_string()
-            },
-            BTreeMap::new(),
-        );
-        for freq in &config.test_freq {
-            queue!(
-            stdout,
-            cursor::MoveTo(0, 0),
-            terminal::Clear(terminal::ClearType::CurrentLine),
-            cursor::MoveToNextLine(1),
-            terminal::Clear(terminal::ClearType::CurrentLine),
-            cursor::MoveTo(0, 0),
-            style::PrintStyledContent("Press the up/down keys to adjust the volume and press Enter the first time you hear nothing.".bold()),
-            cursor::MoveToNextLine(1),
-            style::PrintStyledContent("Current frequency: ".green()),
-            style::Print(format!("{:?} Hz", freq)),
-            style::PrintStyledContent("   LR: ".green()),
-            style::Print(format!("{:?}", if channel == 0 { "L" } else { "R" })),
-            cursor::MoveToNextLine(1),
-            style::Print("If audio signal channel is swapped, press 'r' to reset.".to_string()),
-            cursor::MoveToNextLine(1),
-        )
-        .unwrap();
-            stdout.flush().unwrap();
-
-            // reset sink
-            let mut sink = Sink::try_new(&stream_handle).unwrap();
-            let mut source = sinewave::SineWave::new(*freq, 0.4, 0.4, channel);
-            sink.set_volume(db_to_float(-24.0) as f32);
-            sink.append(source);
-
-            // Loop until enter is pressed
-            // Increase volume by 0.5db when the up arrow key is pressed
-            // Decrease volume by 0.5db when the down arrow key is pressed
-            loop {
-                if let Ok(Event::Key(event)) = read() {
-                    match event.code {
-                        crossterm::event::KeyCode::Up => {
-                            sink.set_volume(sink.volume() * db_to_float(2.0) as f32);
-                        }
-                        crossterm::event::KeyCode::Down => {
-                            sink.set_volume(sink.volume() * db_to_float(-2.0) as f32);
-                        }
-                        crossterm::event::KeyCode::Char('r') => {
-                            sink.stop();
-                            sink = Sink::try_new(&stream_handle).unwrap();
-                            source = sinewave::SineWave::new(*freq, 0.4, 0.4, channel);
-                            sink.set_volume(db_to_float(-24.0) as f32);
-                            sink.append(source);
-                        }
-                        crossterm::event::KeyCode::Enter => {
-                            sink.stop();
-                            // print volume
-                            queue!(
-                                stdout,
-                                cursor::MoveToNextLine(1),
-                                terminal::Clear(terminal::ClearType::CurrentLine),
-                                style::PrintStyledContent(
-                                    format!("Result of {:?} Hz -> Volume: ", *freq as i32).green()
-                                ),
-                                style::Print(format!(
-                                    "{:.3} dB",
-                                    float_to_db(sink.volume() as f64)
-                                )),
-                            )
-                            .unwrap();
-                            stdout.flush().unwrap();
-
-                            // write result
-                            result
-                                .get_mut(if channel == 0 { "L" } else { "R" })
-                                .unwrap()
-                                .insert(*freq as i32, float_to_db(sink.volume() as f64) as f32);
-
-                            // wait 0.5sec
-                            std::thread::sleep(Duration::from_millis(800));
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
-    }
-
-    // print result
-    queue!(
-        stdout,
-        cursor::MoveToNextLine(1),
-        terminal::Clear(terminal::ClearType::CurrentLine),
-        style::PrintStyledContent("Result: ".green()),
-        cursor::MoveToNextLine(1),
-        style::PrintStyledContent("L: ".green()),
-        style::Print(format!("{:?}", result.get("L").unwrap())),
-        cursor::MoveToNextLine(1),
-        style::PrintStyledContent("R: ".green()),
-        style::Print(format!("{:?}", result.get("R").unwrap())),
-    )
-    .unwrap();
-    stdout.flush().unwrap();
-
-    let now_date = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-
-    csv::save_to_csv(result.clone(), "./result", &now_date);
-    // plot
-    plot::plot_audiogram(result.clone(), "./result", &now_date);
 }
 
-fn load_yaml_config(path: &Path) -> Point {
-    // loaf yaml file and load as string
-    let file = File::open(path).unwrap();
-    let reader = BufReader::new(file);
-    let mut yaml_str = String::new();
-    // load lines with LF
-    for line in reader.lines() {
-        yaml_str.push_str(&line.unwrap());
-        yaml_str.push_str(&"\n");
+/// Reads and parses the config file, exiting with the position of the first
+/// syntax or type error. Also returns the YAML text for `validate_or_exit`.
+fn load_yaml_config(path: &Path) -> (Point, String) {
+    let source = fs::read_to_string(path)
+        .unwrap_or_else(|err| fail(&format!("{}: {}", path.display(), err)));
+    match validate::parse(&source) {
+        Ok(config) => (config, source),
+        Err(diagnostic) => fail(&format!("{}:{}", path.display(), diagnostic)),
     }
-
-    // parse yaml string
-    let points: Point = serde_yaml::from_str(&yaml_str).unwrap();
-    return points;
 }
 
-fn float_to_db(float: f64) -> f64 {
-    return 20.0 * float.log10();
+/// Prints the problems `validate::check` finds in `config` and exits if any
+/// of them is an error. Returns the number of warnings.
+fn validate_or_exit(path: &Path, config: &Point, source: Option<&str>) -> usize {
+    let diagnostics = validate::check(config, source);
+    for diagnostic in &diagnostics {
+        eprintln!("{}:{}", path.display(), diagnostic);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        fail(&format!("{}: {} errors", path.display(), errors));
+    }
+    diagnostics.len() - errors
 }
 
 fn db_to_float(db: f64) -> f64 {
-    return 10.0_f64.powf(db / 20.0);
+    10.0_f64.powf(db / 20.0)
 }
@@ -0,0 +1,235 @@
+use rodio::source::Source;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::channel_map::Transducer;
+use crate::db_to_float;
+use crate::gate::Gate;
+use crate::noise::{Noise, NoiseBand};
+
+/// Contralateral masking settings in `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MaskingConfig {
+    /// Effective masking level of the narrowband masker in dBFS, i.e. on the
+    /// same scale as the tone levels.
+    pub effective_level: f64,
+    /// Only re-test a threshold with masking when it is at least this many dB
+    /// worse than the other ear at the same frequency. Without it every
+    /// threshold is measured masked.
+    #[serde(default)]
+    pub criterion: Option<f64>,
+    #[serde(default)]
+    pub seed: u64,
+    /// Search for the masking plateau instead of using `effective_level` only.
+    #[serde(default)]
+    pub plateau: Option<PlateauConfig>,
+    /// Transducer of the channel map that plays the masker. Defaults to the
+    /// test transducer, or the first two-channel one when the test transducer
+    /// has a single output (e.g. headphones masking during bone conduction).
+    #[serde(default)]
+    pub transducer: Option<String>,
+}
+
+impl MaskingConfig {
+    /// The transducer that masks while testing through `test`. It needs an
+    /// output for each ear to reach the non-test one.
+    pub fn transducer<'a>(
+        &self,
+        channel_map: &'a [Transducer],
+        test: &'a Transducer,
+    ) -> Result<&'a Transducer, String> {
+        match &self.transducer {
+            Some(name) => {
+                let transducer = channel_map
+                    .iter()
+                    .find(|transducer| &transducer.transducer == name)
+                    .ok_or_else(|| format!("transducer {:?} is not in the channel map", name))?;
+                if transducer.channels < 2 {
+                    return Err(format!(
+                        "transducer {:?} has one output and cannot reach the other ear",
+                        name
+                    ));
+                }
+                Ok(transducer)
+            }
+            None if test.channels >= 2 => Ok(test),
+            None => channel_map
+                .iter()
+                .find(|transducer| transducer.channels >= 2)
+                .ok_or_else(|| {
+                    format!(
+                        "{} has one output and the channel map has no two-channel transducer",
+                        test.transducer
+                    )
+                }),
+        }
+    }
+}
+
+/// Plateau (Hood) method: the masker is raised in `step`s until the threshold
+/// stays within `tolerance` for `width` consecutive steps.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PlateauConfig {
+    #[serde(default = "default_step")]
+    pub step: f64,
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// Highest masker level in dBFS; the search gives up above it.
+    #[serde(default)]
+    pub max_level: f64,
+}
+
+fn default_step() -> f64 {
+    5.0
+}
+
+fn default_width() -> u32 {
+    3
+}
+
+fn default_tolerance() -> f64 {
+    2.5
+}
+
+/// Narrowband noise in the non-test ear, centred on the test frequency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Masker {
+    /// Level in dBFS.
+    pub level: f64,
+    pub seed: u64,
+    /// Output channel of the non-test ear.
+    pub channel: u16,
+}
+
+impl Masker {
+    /// Linear gain of the masker relative to a tone played at `tone_level`.
+    ///
+    /// Outputs apply the tone level to the whole mix, so this keeps the masker
+    /// at its own level whatever the tone does.
+    pub fn relative_gain(&self, tone_level: f64) -> f32 {
+        db_to_float(self.level - tone_level) as f32
+    }
+}
+
+/// Handle to the masker gain of a playing `MaskedTone`.
+#[derive(Clone, Debug)]
+pub struct MaskerGain {
+    masker: Masker,
+    gain: Arc<AtomicU32>,
+}
+
+impl MaskerGain {
+    pub fn new(masker: Masker, tone_level: f64) -> MaskerGain {
+        let gain = masker.relative_gain(tone_level);
+        MaskerGain {
+            masker,
+            gain: Arc::new(AtomicU32::new(gain.to_bits())),
+        }
+    }
+
+    /// Keeps the masker at its own level after the tone moved to `tone_level`.
+    pub fn set_tone_level(&self, tone_level: f64) {
+        let gain = self.masker.relative_gain(tone_level);
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+}
+
+/// A test tone and a continuous 1/3-octave masker on the masker channel, mixed
+/// into one source.
+pub struct MaskedTone {
+    tone: Box<dyn Source<Item = f32> + Send>,
+    masker: Noise,
+    gain: MaskerGain,
+}
+
+impl MaskedTone {
+    pub fn new(
+        tone: Box<dyn Source<Item = f32> + Send>,
+        freq: f32,
+        gain: MaskerGain,
+    ) -> MaskedTone {
+        let masker = Noise::with_gate(
+            NoiseBand::ThirdOctave(freq),
+            gain.masker.seed,
+            Gate::continuous(gain.masker.channel, tone.channels()),
+        );
+        MaskedTone { tone, masker, gain }
+    }
+}
+
+impl Iterator for MaskedTone {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        let tone = self.tone.next()?;
+        let masker = self.masker.next()?;
+        Some(tone + masker * self.gain.get())
+    }
+}
+
+impl Source for MaskedTone {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.tone.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        48000
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinewave::SineWave;
+
+    #[test]
+    fn test_masker_on_other_channel() {
+        // tone on output 2, masker on output 3 of a 4-channel device
+        let gain = MaskerGain::new(
+            Masker {
+                level: -20.0,
+                seed: 0,
+                channel: 3,
+            },
+            -40.0,
+        );
+        let tone = Box::new(SineWave::continuous(1000.0, 2, 4));
+        let mixed: Vec<f32> = MaskedTone::new(tone, 1000.0, gain.clone())
+            .take(4 * 48000)
+            .collect();
+
+        let rms = |channel: usize| {
+            let samples: Vec<f32> = mixed.iter().skip(channel).step_by(4).copied().collect();
+            (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+        };
+        assert_eq!(rms(0), 0.0);
+        assert_eq!(rms(1), 0.0);
+        // masker 20 dB above the tone
+        assert!((rms(2) - 0.707).abs() < 0.01, "tone {}", rms(2));
+        assert!((rms(3) - 7.07).abs() < 0.5, "masker {}", rms(3));
+
+        gain.set_tone_level(-20.0);
+        assert_eq!(gain.get(), 1.0);
+    }
+}
@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::{Calibration, Scale};
+use crate::procedure::{Procedure, Response};
+use crate::stimulus::StimulusKind;
+
+/// One test run: who was tested, how, and the thresholds found.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Session {
+    /// Start of the session as `%Y%m%d_%H%M%S`, also the stem of its files.
+    pub date: String,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub memo: String,
+    /// Output device, from the calibration profile when there is one.
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub calibration_profile: Option<String>,
+    /// Calibration the levels were converted with.
+    #[serde(default)]
+    pub calibration: Option<Calibration>,
+    /// `Calibration::fingerprint` of `calibration`, kept where the calibration
+    /// itself is not, as in CSV files.
+    #[serde(default)]
+    pub calibration_fingerprint: Option<String>,
+    /// Unknown for sessions loaded from legacy CSV files.
+    #[serde(default)]
+    pub procedure: Option<Procedure>,
+    #[serde(default)]
+    pub stimulus: Option<StimulusKind>,
+    /// Unit of every `ThresholdPoint::level`.
+    pub scale: Scale,
+    /// RFC 3339 timestamps.
+    #[serde(default)]
+    pub started: Option<String>,
+    #[serde(default)]
+    pub finished: Option<String>,
+    pub ears: Vec<EarResult>,
+}
+
+/// Thresholds of one ear through one transducer.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EarResult {
+    pub transducer: String,
+    /// "L" or "R".
+    pub ear: String,
+    /// In test frequency order.
+    pub points: Vec<ThresholdPoint>,
+}
+
+/// The threshold at one frequency.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ThresholdPoint {
+    /// Frequency in Hz.
+    pub freq: f32,
+    /// Threshold in the unit of `Session::scale`.
+    pub level: f32,
+    /// Masker level in dBFS when the threshold was measured with
+    /// contralateral masking.
+    #[serde(default)]
+    pub masker_level: Option<f32>,
+    /// Number of presentations it took.
+    #[serde(default)]
+    pub presentations: u32,
+    /// Number of those the listener heard.
+    #[serde(default)]
+    pub heard: u32,
+    /// Not heard at the highest level allowed: `level` is that level and the
+    /// threshold lies above it.
+    #[serde(default)]
+    pub no_response: bool,
+    /// The masker reached `plateau.max_level` before the threshold stopped
+    /// moving with it, so the other ear may still have heard the tone.
+    #[serde(default)]
+    pub no_plateau: bool,
+    /// Every presentation in order, empty for sessions loaded from CSV files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trials: Vec<Trial>,
+}
+
+/// One presentation and the listener's answer to it.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Trial {
+    /// Stimulus onset, or the level change for adjustment procedures, in
+    /// seconds since the session started.
+    pub onset: f64,
+    /// Level presented, in the unit of `Session::scale`.
+    pub level: f32,
+    /// Masker level in dBFS.
+    #[serde(default)]
+    pub masker_level: Option<f32>,
+    /// The key pressed; `not_heard` when the response window passed without one.
+    pub response: Response,
+    /// Seconds from onset to the response.
+    pub reaction_time: f64,
+}
+
+impl Session {
+    /// An empty session in dBFS.
+    pub fn new(date: &str) -> Session {
+        Session {
+            date: date.to_string(),
+            subject: None,
+            memo: String::new(),
+            device: None,
+            calibration_profile: None,
+            calibration: None,
+            calibration_fingerprint: None,
+            procedure: None,
+            stimulus: None,
+            scale: Scale::Dbfs,
+            started: None,
+            finished: None,
+            ears: Vec::new(),
+        }
+    }
+}
+
+impl ThresholdPoint {
+    /// A threshold without masking or presentation counts, as in legacy CSV
+    /// files.
+    pub fn new(freq: f32, level: f32) -> ThresholdPoint {
+        ThresholdPoint {
+            freq,
+            level,
+            masker_level: None,
+            presentations: 0,
+            heard: 0,
+            no_response: false,
+            no_plateau: false,
+            trials: Vec::new(),
+        }
+    }
+}
+
+impl EarResult {
+    pub fn new(transducer: &str, ear: &str) -> EarResult {
+        EarResult {
+            transducer: transducer.to_string(),
+            ear: ear.to_string(),
+            points: Vec::new(),
+        }
+    }
+
+    /// "<transducer>:<ear>", or just the ear when the transducer is unknown.
+    pub fn key(&self) -> String {
+        if self.transducer.is_empty() {
+            self.ear.clone()
+        } else {
+            format!("{}:{}", self.transducer, self.ear)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_yaml_round_trip() {
+        let mut session = Session::new("20230105_101500");
+        session.procedure = Some(Procedure::HughsonWestlake);
+        let mut ear = EarResult::new("headphone", "L");
+        ear.points.push(ThresholdPoint {
+            masker_level: Some(-40.0),
+            presentations: 1,
+            heard: 1,
+            trials: vec![Trial {
+                onset: 0.8,
+                level: -52.0,
+                masker_level: Some(-40.0),
+                response: Response::Heard,
+                reaction_time: 0.42,
+            }],
+            ..ThresholdPoint::new(62.5, -52.0)
+        });
+        session.ears.push(ear);
+
+        let yaml = serde_yaml::to_string(&session).unwrap();
+        let loaded: Session = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(loaded, session);
+        assert_eq!(loaded.ears[0].key(), "headphone:L");
+    }
+}
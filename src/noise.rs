@@ -0,0 +1,255 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rodio::source::Source;
+
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+use std::time::Duration;
+
+use crate::gate::{Gate, Timing};
+
+/// Spectrum of a `Noise` source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseBand {
+    White,
+    /// -3 dB/octave.
+    Pink,
+    /// 1/3-octave band centred on the given frequency.
+    ThirdOctave(f32),
+}
+
+/// A seedable noise source with the same gating and channel routing as
+/// `SineWave`.
+///
+/// The output is scaled to the RMS of a full-scale sine (1/√2), so a noise
+/// and a tone played at the same dBFS level have the same power.
+#[derive(Clone, Debug)]
+pub struct Noise {
+    rng: StdRng,
+    filter: Filter,
+    scale: f32,
+    gate: Gate,
+}
+
+impl Noise {
+    /// Builds a noise gated in on/off cycles of `timing`.
+    pub fn new(
+        band: NoiseBand,
+        seed: u64,
+        timing: Timing,
+        on_channel: u16,
+        channels: u16,
+    ) -> Noise {
+        Noise::with_gate(band, seed, Gate::new(timing, on_channel, channels))
+    }
+
+    /// Builds a noise with any gate, e.g. `Gate::continuous` for a masker.
+    pub fn with_gate(band: NoiseBand, seed: u64, gate: Gate) -> Noise {
+        let filter = match band {
+            NoiseBand::White => Filter::White,
+            NoiseBand::Pink => Filter::Pink([0.0; 7]),
+            NoiseBand::ThirdOctave(center) => {
+                // Q of a 1/3-octave band: 2^(1/6) / (2^(1/3) - 1)
+                let q = 2.0_f32.powf(1.0 / 6.0) / (2.0_f32.powf(1.0 / 3.0) - 1.0);
+                let biquad = Biquad::bandpass(center, q);
+                Filter::Bandpass([biquad, biquad])
+            }
+        };
+        let mut noise = Noise {
+            rng: StdRng::seed_from_u64(seed),
+            filter,
+            scale: 1.0,
+            gate,
+        };
+
+        // measure the RMS of one second of the same sequence to normalise it
+        let mut probe = noise.clone();
+        let sum_sq: f32 = (0..48000).map(|_| probe.generate().powi(2)).sum();
+        noise.scale = FRAC_1_SQRT_2 / (sum_sq / 48000.0).sqrt();
+        noise
+    }
+
+    /// Next unscaled sample.
+    fn generate(&mut self) -> f32 {
+        // Box-Muller transform of two uniform samples
+        let u1: f32 = 1.0 - self.rng.gen::<f32>();
+        let u2: f32 = self.rng.gen::<f32>();
+        let white = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+
+        match &mut self.filter {
+            Filter::White => white,
+            Filter::Pink(b) => {
+                // Paul Kellet's refined pinking filter
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink
+            }
+            Filter::Bandpass(stages) => stages
+                .iter_mut()
+                .fold(white, |sample, stage| stage.process(sample)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Filter {
+    White,
+    Pink([f32; 7]),
+    /// Two cascaded band-pass stages for steeper skirts.
+    Bandpass([Biquad; 2]),
+}
+
+/// Direct form I biquad.
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    /// Band-pass with 0 dB peak gain (RBJ audio EQ cookbook) at 48kHz.
+    fn bandpass(center: f32, q: f32) -> Biquad {
+        let w0 = 2.0 * PI * center / 48000.0;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b0: alpha / a0,
+            b1: 0.0,
+            b2: -alpha / a0,
+            a1: -2.0 * w0.cos() / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+impl Iterator for Noise {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        Some(match self.gate.next() {
+            // keep generating while gated off so the filters stay settled
+            Some((_, gain)) => gain * self.scale * self.generate(),
+            None => 0.0,
+        })
+    }
+}
+
+impl Source for Noise {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.gate.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        48000
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(noise: Noise, channel: usize, frames: usize) -> Vec<f32> {
+        noise.skip(channel).step_by(2).take(frames).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let a = channel(
+            Noise::new(NoiseBand::Pink, 7, Timing::default(), 0, 2),
+            0,
+            4800,
+        );
+        let b = channel(
+            Noise::new(NoiseBand::Pink, 7, Timing::default(), 0, 2),
+            0,
+            4800,
+        );
+        let c = channel(
+            Noise::new(NoiseBand::Pink, 8, Timing::default(), 0, 2),
+            0,
+            4800,
+        );
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_routing_and_level() {
+        for band in [
+            NoiseBand::White,
+            NoiseBand::Pink,
+            NoiseBand::ThirdOctave(1000.0),
+        ] {
+            let off = channel(Noise::with_gate(band, 1, Gate::continuous(1, 2)), 0, 48000);
+            assert!(off.iter().all(|s| *s == 0.0));
+
+            let on = channel(Noise::with_gate(band, 1, Gate::continuous(1, 2)), 1, 48000);
+            let level = rms(&on);
+            assert!(
+                (level - FRAC_1_SQRT_2).abs() < 0.05,
+                "{:?}: {}",
+                band,
+                level
+            );
+        }
+    }
+
+    #[test]
+    fn test_third_octave_band() {
+        // a tone inside the band passes, one two octaves away is strongly attenuated
+        let mut stages = [Biquad::bandpass(1000.0, 4.318); 2];
+        let mut gain = |freq: f32| {
+            let out: Vec<f32> = (0..48000)
+                .map(|n| (2.0 * PI * freq * n as f32 / 48000.0).sin())
+                .map(|x| stages.iter_mut().fold(x, |s, stage| stage.process(s)))
+                .collect();
+            rms(&out[24000..]) / FRAC_1_SQRT_2
+        };
+        assert!(gain(1000.0) > 0.95);
+        assert!(gain(4000.0) < 0.01);
+    }
+}
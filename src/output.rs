@@ -0,0 +1,302 @@
+use rodio::cpal::traits::HostTrait;
+use rodio::source::Source;
+use rodio::{cpal, DeviceTrait, OutputStream, OutputStreamHandle, Sink};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::db_to_float;
+use crate::masking::MaskerGain;
+use crate::stimulus::Stimulus;
+
+/// Sample rate of every stimulus and output.
+pub const SAMPLE_RATE: u32 = 48000;
+
+/// Where stimuli go.
+///
+/// Levels are in dBFS and applied as a linear gain on top of the stimulus, the
+/// same way `Sink::set_volume` does. A masker keeps its own level.
+pub trait AudioOutput {
+    /// Starts `stimulus` at `level`, replacing whatever was playing.
+    fn play(&mut self, stimulus: &Stimulus, level: f64);
+
+    /// Changes the level of the stimulus currently playing.
+    fn set_level(&mut self, level: f64);
+
+    fn stop(&mut self);
+
+    /// Lets `duration` of output pass. Real-time backends keep playing in the
+    /// background and ignore this; offline backends render it.
+    fn advance(&mut self, duration: Duration);
+
+    /// Time since the output was opened: wall-clock time for real-time
+    /// backends, rendered time for offline ones.
+    fn elapsed(&self) -> Duration;
+}
+
+/// Output backend selected in `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum OutputConfig {
+    /// Play through the default sound device.
+    #[default]
+    Device,
+    /// Render every stimulus into `<dir>/<date>.wav` with a `<dir>/<date>_log.csv` timing log.
+    /// The WAV has one channel per output of the channel map.
+    Wav { dir: String },
+}
+
+impl OutputConfig {
+    /// Opens the backend for a device with `channels` outputs.
+    pub fn open(&self, now_date: &str, channels: u16) -> Result<Box<dyn AudioOutput>, String> {
+        Ok(match self {
+            OutputConfig::Device => Box::new(RodioOutput::new(channels)?),
+            OutputConfig::Wav { dir } => {
+                if fs::metadata(dir).is_err() {
+                    fs::create_dir_all(dir).unwrap();
+                }
+                let dir = Path::new(dir);
+                Box::new(WavRenderer::new(
+                    &dir.join(format!("{}.wav", now_date)),
+                    &dir.join(format!("{}_log.csv", now_date)),
+                    channels,
+                ))
+            }
+        })
+    }
+}
+
+/// Plays stimuli on the default output device through a rodio `Sink`.
+///
+/// rodio opens the device with its default configuration and would silently
+/// drop or fold extra source channels, so a device with fewer outputs than the
+/// channel map is refused.
+pub struct RodioOutput {
+    _stream: OutputStream,
+    stream_handle: OutputStreamHandle,
+    sink: Option<Sink>,
+    masker: Option<MaskerGain>,
+    opened: Instant,
+}
+
+impl RodioOutput {
+    pub fn new(channels: u16) -> Result<RodioOutput, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no output device")?;
+        let name = device.name().unwrap_or_default();
+        let outputs = device.default_output_config().unwrap().channels();
+        if outputs < channels {
+            return Err(format!(
+                "output device {:?} has {} outputs but the channel map needs {}",
+                name, outputs, channels
+            ));
+        }
+        let (_stream, stream_handle) = OutputStream::try_from_device(&device).unwrap();
+        Ok(RodioOutput {
+            _stream,
+            stream_handle,
+            sink: None,
+            masker: None,
+            opened: Instant::now(),
+        })
+    }
+}
+
+impl AudioOutput for RodioOutput {
+    fn play(&mut self, stimulus: &Stimulus, level: f64) {
+        self.stop();
+        let sink = Sink::try_new(&self.stream_handle).unwrap();
+        sink.set_volume(db_to_float(level) as f32);
+        let (source, gain) = stimulus.build(level);
+        sink.append(source);
+        self.sink = Some(sink);
+        self.masker = gain;
+    }
+
+    fn set_level(&mut self, level: f64) {
+        if let Some(sink) = &self.sink {
+            sink.set_volume(db_to_float(level) as f32);
+        }
+        if let Some(masker) = &self.masker {
+            masker.set_tone_level(level);
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        self.masker = None;
+    }
+
+    fn advance(&mut self, _duration: Duration) {}
+
+    fn elapsed(&self) -> Duration {
+        self.opened.elapsed()
+    }
+}
+
+/// Renders the session offline into a multi-channel 32-bit float WAV file.
+///
+/// Every `play`/`set_level`/`stop` is written to a CSV log with the sample
+/// index it took effect at, so the WAV can be checked sample by sample.
+pub struct WavRenderer {
+    writer: hound::WavWriter<BufWriter<File>>,
+    log: BufWriter<File>,
+    channels: u16,
+    sample_rate: u32,
+    current: Option<(Box<dyn Source<Item = f32> + Send>, f32)>,
+    stimulus: Option<Stimulus>,
+    masker: Option<MaskerGain>,
+    elapsed: Duration,
+    written_frames: u64,
+}
+
+impl WavRenderer {
+    pub fn new(wav_path: &Path, log_path: &Path, channels: u16) -> WavRenderer {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let writer = hound::WavWriter::create(wav_path, spec).unwrap();
+        let mut log = BufWriter::new(File::create(log_path).unwrap());
+        writeln!(log, "sample,time_sec,event,freq,channel,level_db,masker_db").unwrap();
+        WavRenderer {
+            writer,
+            log,
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+            current: None,
+            stimulus: None,
+            masker: None,
+            elapsed: Duration::ZERO,
+            written_frames: 0,
+        }
+    }
+
+    fn write_log(&mut self, event: &str, level: Option<f64>) {
+        let (freq, channel, masker) = match &self.stimulus {
+            Some(stimulus) => (
+                stimulus.freq.to_string(),
+                stimulus.channel.to_string(),
+                stimulus
+                    .masker
+                    .map(|masker| masker.level.to_string())
+                    .unwrap_or_default(),
+            ),
+            None => (String::new(), String::new(), String::new()),
+        };
+        writeln!(
+            self.log,
+            "{},{:.6},{},{},{},{},{}",
+            self.written_frames,
+            self.written_frames as f64 / self.sample_rate as f64,
+            event,
+            freq,
+            channel,
+            level.map(|level| level.to_string()).unwrap_or_default(),
+            masker,
+        )
+        .unwrap();
+    }
+}
+
+impl AudioOutput for WavRenderer {
+    fn play(&mut self, stimulus: &Stimulus, level: f64) {
+        let (source, gain) = stimulus.build(level);
+        assert_eq!(source.channels(), self.channels);
+        assert_eq!(source.sample_rate(), self.sample_rate);
+        self.current = Some((source, db_to_float(level) as f32));
+        self.stimulus = Some(*stimulus);
+        self.masker = gain;
+        self.write_log("play", Some(level));
+    }
+
+    fn set_level(&mut self, level: f64) {
+        if let Some((_, gain)) = &mut self.current {
+            *gain = db_to_float(level) as f32;
+            self.write_log("level", Some(level));
+        }
+        if let Some(masker) = &self.masker {
+            masker.set_tone_level(level);
+        }
+    }
+
+    fn stop(&mut self) {
+        self.masker = None;
+        if self.current.take().is_some() {
+            self.write_log("stop", None);
+        }
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        // render up to the total elapsed time so rounding never accumulates
+        self.elapsed += duration;
+        let target_frames = (self.elapsed.as_secs_f64() * self.sample_rate as f64).round() as u64;
+        while self.written_frames < target_frames {
+            for _ in 0..self.channels {
+                let sample = match &mut self.current {
+                    Some((source, gain)) => source.next().unwrap_or(0.0) * *gain,
+                    None => 0.0,
+                };
+                self.writer.write_sample(sample).unwrap();
+            }
+            self.written_frames += 1;
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate::Timing;
+    use crate::stimulus::StimulusKind;
+
+    #[test]
+    fn test_wav_renderer() {
+        let dir = std::env::temp_dir().join("hearing-test-wav-renderer");
+        fs::create_dir_all(&dir).unwrap();
+        let wav_path = dir.join("render.wav");
+        let log_path = dir.join("render_log.csv");
+
+        let mut renderer = WavRenderer::new(&wav_path, &log_path, 2);
+        renderer.advance(Duration::from_millis(100));
+        renderer.play(
+            &Stimulus::new(StimulusKind::Sine, 1000.0, 0, 2, Timing::default()),
+            -20.0,
+        );
+        renderer.advance(Duration::from_millis(500));
+        renderer.stop();
+        renderer.advance(Duration::from_millis(100));
+        drop(renderer);
+
+        let mut reader = hound::WavReader::open(&wav_path).unwrap();
+        let samples: Vec<f32> = reader.samples::<f32>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples.len(), 2 * 48000 * 7 / 10);
+
+        let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+        let right: Vec<f32> = samples.iter().skip(1).step_by(2).copied().collect();
+        assert!(right.iter().all(|s| *s == 0.0));
+        assert!(left[..4800].iter().all(|s| *s == 0.0));
+        assert!(left[28800..].iter().all(|s| *s == 0.0));
+        let peak = left.iter().fold(0.0_f32, |a, s| a.max(s.abs()));
+        assert!((peak - 0.1).abs() < 0.001, "peak was {}", peak);
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "4800,0.100000,play,1000,0,-20,");
+        assert_eq!(lines[2], "28800,0.600000,stop,1000,0,,");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+use plotters_backend::text_anchor::{HPos, VPos};
+use plotters_backend::{
+    BackendColor, BackendCoord, BackendStyle, BackendTextStyle, DrawingBackend, DrawingErrorKind,
+    FontTransform,
+};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A4 portrait in points.
+pub const A4: (u32, u32) = (595, 842);
+
+/// A plotters backend that writes a one-page vector PDF.
+///
+/// Backend units are PDF points (1/72 in) with the origin at the top left, as
+/// for the other backends. Text is set in the standard Helvetica font, which
+/// every reader has, so nothing is embedded; characters outside Latin-1 are
+/// replaced by '?'. Translucent colours are blended against white.
+pub struct PdfBackend {
+    path: PathBuf,
+    size: (u32, u32),
+    content: String,
+    saved: bool,
+}
+
+impl PdfBackend {
+    pub fn new(path: &Path, size: (u32, u32)) -> PdfBackend {
+        PdfBackend {
+            path: path.to_path_buf(),
+            size,
+            content: String::new(),
+            saved: false,
+        }
+    }
+
+    /// PDF y axis points up from the bottom of the page.
+    fn point(&self, (x, y): BackendCoord) -> (i32, i32) {
+        (x, self.size.1 as i32 - y)
+    }
+
+    fn set_color(&mut self, color: BackendColor, operator: &str) {
+        let blend = |c: u8| (c as f64 * color.alpha + 255.0 * (1.0 - color.alpha)) / 255.0;
+        let (r, g, b) = color.rgb;
+        writeln!(
+            self.content,
+            "{:.3} {:.3} {:.3} {}",
+            blend(r),
+            blend(g),
+            blend(b),
+            operator
+        )
+        .unwrap();
+    }
+
+    fn stroke_style<S: BackendStyle>(&mut self, style: &S) {
+        self.set_color(style.color(), "RG");
+        writeln!(self.content, "{} w", style.stroke_width().max(1)).unwrap();
+    }
+
+    fn path<I: IntoIterator<Item = BackendCoord>>(&mut self, path: I) {
+        for (i, coord) in path.into_iter().enumerate() {
+            let (x, y) = self.point(coord);
+            let operator = if i == 0 { "m" } else { "l" };
+            writeln!(self.content, "{} {} {}", x, y, operator).unwrap();
+        }
+    }
+
+    /// The complete file: catalog, page tree, page, font and content stream.
+    fn document(&self) -> Vec<u8> {
+        let content = encode(&self.content);
+        let objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 4 0 R >> >> /Contents 5 0 R >>",
+                self.size.0, self.size.1
+            )
+            .into_bytes(),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .to_vec(),
+            [
+                format!("<< /Length {} >>\nstream\n", content.len()).into_bytes(),
+                content,
+                b"\nendstream".to_vec(),
+            ]
+            .concat(),
+        ];
+
+        let mut document = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(document.len());
+            document.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            document.extend_from_slice(object);
+            document.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = document.len();
+        let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            writeln!(trailer, "{:010} 00000 n ", offset).unwrap();
+        }
+        write!(
+            trailer,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .unwrap();
+        document.extend_from_slice(trailer.as_bytes());
+        document
+    }
+}
+
+/// Latin-1 bytes of `text`; WinAnsi matches Latin-1 for the printable range.
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+        .collect()
+}
+
+/// `text` as a PDF string literal.
+fn literal(text: &str) -> String {
+    let mut literal = String::from("(");
+    for c in text.chars() {
+        if matches!(c, '(' | ')' | '\\') {
+            literal.push('\\');
+        }
+        literal.push(c);
+    }
+    literal.push(')');
+    literal
+}
+
+/// Approximate width of `text` in Helvetica, in units of the font size.
+fn text_width(text: &str) -> f64 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '\'' | '|' | '!' => 0.25,
+            'f' | 't' | 'r' | ' ' | '(' | ')' | '[' | ']' | '-' | '/' => 0.33,
+            'm' | 'w' | 'M' | 'W' => 0.85,
+            'A'..='Z' => 0.68,
+            _ => 0.55,
+        })
+        .sum()
+}
+
+impl DrawingBackend for PdfBackend {
+    type ErrorType = io::Error;
+
+    fn get_size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn ensure_prepared(&mut self) -> Result<(), DrawingErrorKind<io::Error>> {
+        Ok(())
+    }
+
+    fn present(&mut self) -> Result<(), DrawingErrorKind<io::Error>> {
+        fs::write(&self.path, self.document()).map_err(DrawingErrorKind::DrawingError)?;
+        self.saved = true;
+        Ok(())
+    }
+
+    fn draw_pixel(
+        &mut self,
+        point: BackendCoord,
+        color: BackendColor,
+    ) -> Result<(), DrawingErrorKind<io::Error>> {
+        self.draw_rect(point, (point.0 + 1, point.1 + 1), &color, true)
+    }
+
+    fn draw_line<S: BackendStyle>(
+        &mut self,
+        from: BackendCoord,
+        to: BackendCoord,
+        style: &S,
+    ) -> Result<(), DrawingErrorKind<io::Error>> {
+        self.draw_path([from, to], style)
+    }
+
+    fn draw_rect<S: BackendStyle>(
+        &mut self,
+        upper_left: BackendCoord,
+        bottom_right: BackendCoord,
+        style: &S,
+        fill: bool,
+    ) -> Result<(), DrawingErrorKind<io::Error>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+        let (left, top) = self.point(upper_left);
+        let (right, bottom) = self.point(bottom_right);
+        if fill {
+            self.set_color(style.color(), "rg");
+        } else {
+            self.stroke_style(style);
+        }
+        writeln!(
+            self.content,
+            "{} {} {} {} re {}",
+            left,
+            bottom,
+            right - left,
+            top - bottom,
+            if fill { "f" } else { "S" }
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    fn draw_path<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
+        &mut self,
+        path: I,
+        style: &S,
+    ) -> Result<(), DrawingErrorKind<io::Error>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+        self.stroke_style(style);
+        self.path(path);
+        self.content.push_str("S\n");
+        Ok(())
+    }
+
+    fn draw_circle<S: BackendStyle>(
+        &mut self,
+        center: BackendCoord,
+        radius: u32,
+        style: &S,
+        fill: bool,
+    ) -> Result<(), DrawingErrorKind<io::Error>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+        if fill {
+            self.set_color(style.color(), "rg");
+        } else {
+            self.stroke_style(style);
+        }
+        // four Bézier quarter arcs
+        let (x, y) = self.point(center);
+        let (x, y, r) = (x as f64, y as f64, radius as f64);
+        let k = 0.5523 * r;
+        writeln!(self.content, "{} {} m", x + r, y).unwrap();
+        for (c1, c2, end) in [
+            ((x + r, y + k), (x + k, y + r), (x, y + r)),
+            ((x - k, y + r), (x - r, y + k), (x - r, y)),
+            ((x - r, y - k), (x - k, y - r), (x, y - r)),
+            ((x + k, y - r), (x + r, y - k), (x + r, y)),
+        ] {
+            writeln!(
+                self.content,
+                "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c",
+                c1.0, c1.1, c2.0, c2.1, end.0, end.1
+            )
+            .unwrap();
+        }
+        self.content.push_str(if fill { "f\n" } else { "S\n" });
+        Ok(())
+    }
+
+    fn fill_polygon<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
+        &mut self,
+        vert: I,
+        style: &S,
+    ) -> Result<(), DrawingErrorKind<io::Error>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+        self.set_color(style.color(), "rg");
+        self.path(vert);
+        self.content.push_str("h f\n");
+        Ok(())
+    }
+
+    fn draw_text<TStyle: BackendTextStyle>(
+        &mut self,
+        text: &str,
+        style: &TStyle,
+        pos: BackendCoord,
+    ) -> Result<(), DrawingErrorKind<io::Error>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+        let size = style.size();
+        let width = text_width(text) * size;
+        // offsets along and across the text direction, from the anchor to the
+        // start of the baseline
+        let along = match style.anchor().h_pos {
+            HPos::Left => 0.0,
+            HPos::Center => -width / 2.0,
+            HPos::Right => -width,
+        };
+        let across = match style.anchor().v_pos {
+            VPos::Top => 0.75 * size,
+            VPos::Center => 0.35 * size,
+            VPos::Bottom => 0.0,
+        };
+        // text direction in PDF coordinates (y up)
+        let (cos, sin) = match style.transform() {
+            FontTransform::None => (1.0, 0.0),
+            FontTransform::Rotate90 => (0.0, -1.0),
+            FontTransform::Rotate180 => (-1.0, 0.0),
+            FontTransform::Rotate270 => (0.0, 1.0),
+        };
+        let (x, y) = self.point(pos);
+        let x = x as f64 + along * cos + across * sin;
+        let y = y as f64 + along * sin - across * cos;
+        self.set_color(style.color(), "rg");
+        writeln!(
+            self.content,
+            "BT /F1 {:.1} Tf {} {} {} {} {:.2} {:.2} Tm {} Tj ET",
+            size,
+            cos,
+            sin,
+            -sin,
+            cos,
+            x,
+            y,
+            literal(text)
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    fn estimate_text_size<TStyle: BackendTextStyle>(
+        &self,
+        text: &str,
+        style: &TStyle,
+    ) -> Result<(u32, u32), DrawingErrorKind<io::Error>> {
+        let size = style.size();
+        Ok(((text_width(text) * size) as u32, size as u32))
+    }
+}
+
+impl Drop for PdfBackend {
+    fn drop(&mut self) {
+        if !self.saved {
+            // errors cannot be reported from here; `present` reports them
+            let _ = self.present();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plotters::prelude::*;
+
+    #[test]
+    fn test_pdf_page() {
+        let path = std::env::temp_dir().join("hearing-test-page.pdf");
+        let root = PdfBackend::new(&path, A4).into_drawing_area();
+        root.fill(&WHITE).unwrap();
+        root.draw(&Text::new(
+            "Threshold (dB HL)",
+            (40, 40),
+            ("sans-serif", 12).into_font(),
+        ))
+        .unwrap();
+        root.draw(&PathElement::new(vec![(40, 60), (200, 60)], RED))
+            .unwrap();
+        root.present().unwrap();
+        drop(root);
+
+        let document = fs::read(&path).unwrap();
+        let text = String::from_utf8_lossy(&document);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/MediaBox [0 0 595 842]"));
+        assert!(text.contains("(Threshold \\(dB HL\\)) Tj"));
+        // y is flipped: 60 pt from the top of a 842 pt page
+        assert!(text.contains("40 782 m\n200 782 l\nS"));
+        // the cross-reference table points at every object
+        let xref = text.rfind("startxref\n").unwrap();
+        let offset: usize = text[xref + 10..].lines().next().unwrap().parse().unwrap();
+        assert!(text[offset..].starts_with("xref\n0 6\n"));
+        for object in 1..=5 {
+            let position = text.find(&format!("\n{} 0 obj", object)).unwrap() + 1;
+            assert!(text.contains(&format!("{:010} 00000 n", position)));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
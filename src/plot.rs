@@ -1,101 +1,1220 @@
+use plotters::coord::Shift;
 use plotters::{prelude::*, series};
-use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
 use std::fs;
+use std::ops::Range;
+use std::path::Path;
 
-// plot audiogram result
+use crate::analysis::{self, Finding, FindingKind, ASHA_DEGREES};
+use crate::calibration::Scale;
+use crate::model::Session;
+use crate::pdf::{self, PdfBackend};
+use crate::trend::{self, HistoryConfig};
+
+/// How audiograms are drawn, the `plot` section of `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct PlotConfig {
+    #[serde(default)]
+    pub style: AudiogramStyle,
+    /// Shade the degrees of hearing loss behind a clinical audiogram in dB HL.
+    #[serde(default)]
+    pub severity_bands: bool,
+    #[serde(default)]
+    pub format: PlotFormat,
+    /// Width and height in pixels, or points for PDF. Defaults to a size that
+    /// suits the style, and A4 for PDF.
+    #[serde(default)]
+    pub size: Option<(u32, u32)>,
+    #[serde(default)]
+    pub history: HistoryConfig,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PlotFormat {
+    /// `<date>_audiogram.png`.
+    #[default]
+    Png,
+    /// `<date>_audiogram.svg`.
+    Svg,
+    /// `<date>_report.pdf`, a printable page with the audiogram, the session
+    /// details, the calibration and a table of the thresholds.
+    Pdf,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AudiogramStyle {
+    /// 0 dB at the top with loss increasing downward, octave frequency ticks
+    /// and the ASHA symbols for each ear, transducer and masking.
+    #[default]
+    Clinical,
+    /// Coloured line series with the level magnitude growing upward.
+    Classic,
+}
+
+/// Audiogram symbols (ASHA 1990), drawn in red for the right ear and blue for
+/// the left.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Symbol {
+    /// Right O, left X.
+    Air,
+    /// Right triangle, left square.
+    AirMasked,
+    /// Right <, left >, beside the frequency line.
+    Bone,
+    /// Right [, left ].
+    BoneMasked,
+    /// S, for loudspeakers.
+    SoundField,
+}
+
+impl Symbol {
+    fn of(transducer: &str, masked: bool) -> Symbol {
+        match (transducer, masked) {
+            ("bone", false) => Symbol::Bone,
+            ("bone", true) => Symbol::BoneMasked,
+            ("speaker", _) | ("sound_field", _) => Symbol::SoundField,
+            (_, false) => Symbol::Air,
+            (_, true) => Symbol::AirMasked,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Symbol::Air => "air",
+            Symbol::AirMasked => "air, masked",
+            Symbol::Bone => "bone",
+            Symbol::BoneMasked => "bone, masked",
+            Symbol::SoundField => "sound field",
+        }
+    }
+}
+
+/// The symbol for `ear` as one pen stroke around `at`, in backend pixels.
+/// Drawn as plain paths so that chart points and legend entries share it.
+fn marker(
+    at: (i32, i32),
+    symbol: Symbol,
+    ear: &str,
+    color: RGBColor,
+    beside: bool,
+) -> PathElement<(i32, i32)> {
+    let right = ear == "R";
+    // bone symbols open toward the ear's side and, on the chart, sit left
+    // (right ear) or right (left ear) of the frequency
+    let side = if right { -1 } else { 1 };
+    let shift = if beside { 9 * side } else { 0 };
+    let points: Vec<(i32, i32)> = match (symbol, right) {
+        (Symbol::Air, true) => (0..=16)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::PI / 8.0;
+                (
+                    (6.0 * angle.cos()).round() as i32,
+                    (6.0 * angle.sin()).round() as i32,
+                )
+            })
+            .collect(),
+        // one stroke, back through the centre
+        (Symbol::Air, false) => vec![(-6, -6), (6, 6), (0, 0), (-6, 6), (6, -6)],
+        (Symbol::AirMasked, true) => vec![(0, -7), (7, 5), (-7, 5), (0, -7)],
+        (Symbol::AirMasked, false) => vec![(-6, -6), (6, -6), (6, 6), (-6, 6), (-6, -6)],
+        (Symbol::Bone, _) => vec![
+            (shift - 4 * side, -7),
+            (shift + 4 * side, 0),
+            (shift - 4 * side, 7),
+        ],
+        (Symbol::BoneMasked, _) => vec![
+            (shift - 3 * side, -7),
+            (shift + 3 * side, -7),
+            (shift + 3 * side, 7),
+            (shift - 3 * side, 7),
+        ],
+        (Symbol::SoundField, _) => vec![(5, -7), (-5, -7), (-5, 0), (5, 0), (5, 7), (-5, 7)],
+    };
+    let points = points
+        .into_iter()
+        .map(|(x, y)| (at.0 + x, at.1 + y))
+        .collect::<Vec<_>>();
+    PathElement::new(points, color.stroke_width(2))
+}
+
+/// The no-response arrow below the symbol at `at`, pointing down and away
+/// from the frequency toward the ear's side (down-left for the right ear).
+fn no_response_arrow(at: (i32, i32), ear: &str, color: RGBColor) -> PathElement<(i32, i32)> {
+    let side = if ear == "R" { -1 } else { 1 };
+    let tip = (at.0 + 14 * side, at.1 + 22);
+    PathElement::new(
+        vec![
+            (at.0 + 4 * side, at.1 + 7),
+            tip,
+            (tip.0 - 8 * side, tip.1 - 2),
+            tip,
+            (tip.0 - side, tip.1 - 9),
+        ],
+        color.stroke_width(2),
+    )
+}
+
+/// Draws the audiogram of `session` into `dir_path` in the style and format
+/// of `config`, annotated with `findings`.
 pub fn plot_audiogram(
-    result: BTreeMap<String, BTreeMap<i32, f32>>,
+    session: &Session,
     dir_path: &str,
-    now_date: &str,
+    config: &PlotConfig,
+    findings: &[Finding],
 ) {
     // if dir_path is not exist, create dir
-    if !fs::metadata(dir_path).is_ok() {
+    if fs::metadata(dir_path).is_err() {
         fs::create_dir(dir_path).unwrap();
     }
 
-    // use now as filename and png
-    let filename = format!("{}_audiogram.png", now_date);
-    let path_str = format!("{}/{}", dir_path, filename);
-    let root = BitMapBackend::new(&path_str, (960, 720)).into_drawing_area();
+    // room for the averages and findings below the chart
+    let strip = 16 * caption_lines(session, findings).len() as u32 + 8;
+    let default_size = match config.style {
+        AudiogramStyle::Clinical => (800, 860 + strip),
+        AudiogramStyle::Classic => (960, 720 + strip),
+    };
+    match config.format {
+        PlotFormat::Png => {
+            let filename = format!("{}_audiogram.png", session.date);
+            let path_str = format!("{}/{}", dir_path, filename);
+            let root = BitMapBackend::new(&path_str, config.size.unwrap_or(default_size))
+                .into_drawing_area();
+            draw_audiogram(&root, session, &filename, config, findings);
+            root.present().unwrap();
+        }
+        PlotFormat::Svg => {
+            let filename = format!("{}_audiogram.svg", session.date);
+            let path_str = format!("{}/{}", dir_path, filename);
+            let root =
+                SVGBackend::new(&path_str, config.size.unwrap_or(default_size)).into_drawing_area();
+            draw_audiogram(&root, session, &filename, config, findings);
+            root.present().unwrap();
+        }
+        PlotFormat::Pdf => {
+            let path = Path::new(dir_path).join(format!("{}_report.pdf", session.date));
+            let root = PdfBackend::new(&path, config.size.unwrap_or(pdf::A4)).into_drawing_area();
+            draw_report(&root, session, config, findings);
+            root.present().unwrap();
+        }
+    }
+}
+
+/// The averages per transducer and ear, then every finding with the rule
+/// that fired.
+fn caption_lines(session: &Session, findings: &[Finding]) -> Vec<String> {
+    let mut lines: Vec<String> = analysis::summarize(session)
+        .iter()
+        .map(|summary| summary.describe(session.scale))
+        .collect();
+    for finding in findings {
+        lines.push(finding.describe());
+        lines.push(format!("    rule: {}", finding.rule));
+    }
+    lines
+}
+
+/// The chart in the style of `config` with `caption_lines` below it.
+fn draw_audiogram<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    session: &Session,
+    caption: &str,
+    config: &PlotConfig,
+    findings: &[Finding],
+) {
+    let lines = caption_lines(session, findings);
+    let (width, height) = root.dim_in_pixel();
+    // smaller on narrow areas such as the report page, so the rules fit
+    let size = (width as i32 / 64).clamp(7, 13);
+    let line_height = size + 3;
+    let (chart, strip) =
+        root.split_vertically(height as i32 - line_height * lines.len() as i32 - 8);
+    strip.fill(&WHITE).unwrap();
+    for (i, line) in lines.iter().enumerate() {
+        strip
+            .draw(&Text::new(
+                line.clone(),
+                (10, line_height * i as i32),
+                ("sans-serif", size).into_font(),
+            ))
+            .unwrap();
+    }
+    match config.style {
+        AudiogramStyle::Clinical => {
+            draw_clinical(&chart, session, caption, config.severity_bands, findings)
+        }
+        AudiogramStyle::Classic => draw_classic(&chart, session, caption),
+    }
+}
+
+/// One page with the session details, the calibration the levels rest on,
+/// the audiogram and its thresholds as a table.
+fn draw_report<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    session: &Session,
+    config: &PlotConfig,
+    findings: &[Finding],
+) {
+    const LINE: i32 = 14;
     root.fill(&WHITE).unwrap();
+    let page = root.margin(36, 36, 40, 40);
+    let page = page
+        .titled("Hearing test report", ("sans-serif", 18).into_font())
+        .unwrap();
+    let text = ("sans-serif", 10).into_font();
+    let bold = ("sans-serif", 10).into_font().style(FontStyle::Bold);
 
-    // result is like this:
-    // {
-    //    "L": {62: -24.0, 125: -24.0, 250: -24.0, 500: -24.0, 1000: -24.0, 1500: -24.0, 2000: -24.0, 3000: -24.0, 4000: -24.0, 6000: -24.0, 8000: -24.0, 10000: -24.0, 12000: -24.0},
-    //    "R": {62: -24.0, 125: -24.0, 250: -24.0, 500: -24.0, 1000: -24.0, 1500: -24.0, 2000: -24.0, 3000: -24.0, 4000: -24.0, 6000: -24.0, 8000: -24.0, 10000: -24.0, 12000: -24.0}
-    //}
+    let details = report_details(session);
+    let (details_area, rest) = page.split_vertically(LINE * details.len() as i32 + LINE);
+    for (i, (label, value)) in details.iter().enumerate() {
+        let y = LINE * i as i32;
+        details_area
+            .draw(&Text::new(label.to_string(), (0, y), bold.clone()))
+            .unwrap();
+        details_area
+            .draw(&Text::new(value.clone(), (110, y), text.clone()))
+            .unwrap();
+    }
 
-    let mut chart_builder = ChartBuilder::on(&root);
+    // as many frequencies per block of the table as fit the widest cell
+    let calibration = calibration_lines(session);
+    let (freqs, rows) = threshold_table(session);
+    let headers: Vec<String> = freqs.iter().map(|freq| format!("{} Hz", freq)).collect();
+    let text_width = |value: &str, font: &FontDesc| {
+        rest.estimate_text_size(value, &font.clone().into())
+            .unwrap()
+            .0 as i32
+    };
+    let widest = headers
+        .iter()
+        .map(|header| text_width(header, &bold))
+        .chain(
+            rows.iter()
+                .flat_map(|(_, cells)| cells.iter())
+                .map(|cell| text_width(cell, &text)),
+        )
+        .max()
+        .unwrap_or(0);
+    let first = 90;
+    let (width, height) = rest.dim_in_pixel();
+    let per_block = ((width as i32 - first) / (widest + 10)).max(1) as usize;
+    let blocks = freqs.len().div_ceil(per_block).max(1);
+    let block_height = LINE * (rows.len() as i32 + 2);
+    let table_height = LINE * 2 + block_height * blocks as i32;
+    let calibration_height = LINE * (calibration.len() as i32 + 2);
+    let (chart_area, rest) =
+        rest.split_vertically(height as i32 - table_height - calibration_height);
+    draw_audiogram(&chart_area, session, &session.date, config, findings);
+
+    let (table_area, calibration_area) = rest.split_vertically(table_height);
+    let column = (width as i32 - first) / per_block as i32;
+    let top = LINE / 2;
+    table_area
+        .draw(&Text::new(
+            format!("Thresholds [{}]", session.scale.unit()),
+            (0, top),
+            bold.clone(),
+        ))
+        .unwrap();
+    for block in 0..blocks {
+        let columns = block * per_block..((block + 1) * per_block).min(freqs.len());
+        let header_y = top + LINE + 4 + block_height * block as i32;
+        for (i, header) in headers[columns.clone()].iter().enumerate() {
+            table_area
+                .draw(&Text::new(
+                    header.clone(),
+                    (first + column * i as i32, header_y),
+                    bold.clone(),
+                ))
+                .unwrap();
+        }
+        let rule = header_y + LINE - 2;
+        table_area
+            .draw(&PathElement::new(
+                vec![(0, rule), (width as i32, rule)],
+                BLACK.mix(0.5),
+            ))
+            .unwrap();
+        for (row, (key, cells)) in rows.iter().enumerate() {
+            let y = rule + 3 + LINE * row as i32;
+            table_area
+                .draw(&Text::new(key.clone(), (0, y), bold.clone()))
+                .unwrap();
+            for (i, cell) in cells[columns.clone()].iter().enumerate() {
+                table_area
+                    .draw(&Text::new(
+                        cell.clone(),
+                        (first + column * i as i32, y),
+                        text.clone(),
+                    ))
+                    .unwrap();
+            }
+        }
+    }
+
+    calibration_area
+        .draw(&Text::new("Calibration", (0, 0), bold.clone()))
+        .unwrap();
+    for (i, line) in calibration.iter().enumerate() {
+        calibration_area
+            .draw(&Text::new(
+                line.clone(),
+                (0, LINE * (i as i32 + 1)),
+                text.clone(),
+            ))
+            .unwrap();
+    }
+}
+
+/// Label and value of each session detail in the report.
+fn report_details(session: &Session) -> Vec<(&'static str, String)> {
+    let or_dash = |value: Option<&str>| value.unwrap_or("-").to_string();
+    // the config spelling, e.g. "hughson_westlake"
+    let procedure = session.procedure.map(|procedure| {
+        serde_json::to_value(procedure)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    });
+    let stimulus = session.stimulus.map(|stimulus| {
+        serde_json::to_value(stimulus).unwrap()["kind"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    });
+    vec![
+        ("Subject", or_dash(session.subject.as_deref())),
+        ("Session", session.date.clone()),
+        ("Started", or_dash(session.started.as_deref())),
+        ("Finished", or_dash(session.finished.as_deref())),
+        ("Procedure", or_dash(procedure.as_deref())),
+        ("Stimulus", or_dash(stimulus.as_deref())),
+        ("Device", or_dash(session.device.as_deref())),
+        (
+            "Calibration profile",
+            or_dash(session.calibration_profile.as_deref()),
+        ),
+        ("Memo", session.memo.clone()),
+    ]
+}
+
+/// The headphone, RETSPL table and measured offsets the hearing levels were
+/// converted with.
+fn calibration_lines(session: &Session) -> Vec<String> {
+    let Some(calibration) = &session.calibration else {
+        return match &session.calibration_fingerprint {
+            // loaded from a CSV file, which keeps only the fingerprint
+            Some(fingerprint) => vec![format!("Calibration {}, tables not recorded", fingerprint)],
+            None => vec!["uncalibrated, levels are in dBFS".to_string()],
+        };
+    };
+    let mut lines = vec![
+        format!(
+            "Headphone: {} ({})",
+            calibration.headphone, calibration.transducer
+        ),
+        format!("RETSPL: {}", calibration.retspl.source()),
+        format!("Fingerprint: {}", calibration.fingerprint()),
+    ];
+    lines.push("Measured dB SPL of a 0 dBFS tone:".to_string());
+    for (ear, table) in calibration.offsets.tables() {
+        let offsets: Vec<String> = table
+            .iter()
+            .map(|(freq, spl)| format!("{} Hz {:.1}", freq, spl))
+            .collect();
+        for (i, chunk) in offsets.chunks(7).enumerate() {
+            match ear {
+                Some(ear) if i == 0 => lines.push(format!("{}: {}", ear, chunk.join(", "))),
+                Some(_) => lines.push(format!("   {}", chunk.join(", "))),
+                None => lines.push(chunk.join(", ")),
+            }
+        }
+    }
+    lines
+}
+
+/// Every tested frequency in ascending order, and per transducer and ear its
+/// threshold at each of them. Masked thresholds are marked with "m", or "m?"
+/// when no plateau was reached, levels without a response with "nr".
+fn threshold_table(session: &Session) -> (Vec<f32>, Vec<(String, Vec<String>)>) {
+    let mut freqs: Vec<f32> = session
+        .ears
+        .iter()
+        .flat_map(|ear| ear.points.iter().map(|point| point.freq))
+        .collect();
+    freqs.sort_by(f32::total_cmp);
+    freqs.dedup();
+    let rows = session
+        .ears
+        .iter()
+        .map(|ear| {
+            let cells = freqs
+                .iter()
+                .map(
+                    |freq| match ear.points.iter().find(|point| point.freq == *freq) {
+                        Some(point) => {
+                            let mut cell = format!("{:.1}", point.level);
+                            if point.no_plateau {
+                                cell.push_str(" m?");
+                            } else if point.masker_level.is_some() {
+                                cell.push_str(" m");
+                            }
+                            if point.no_response {
+                                cell.push_str(" nr");
+                            }
+                            cell
+                        }
+                        None => "-".to_string(),
+                    },
+                )
+                .collect();
+            (ear.key(), cells)
+        })
+        .collect();
+    (freqs, rows)
+}
+
+/// Audiogram after ASHA/ISO 8253-1 conventions: levels grow downward from the
+/// top, one octave spans as much as 20 dB, air conduction thresholds of each
+/// ear are joined (right solid, left dashed) and bone conduction and sound
+/// field thresholds stand alone. dBFS results are drawn the same way, the
+/// most attenuated level at the top.
+fn draw_clinical<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    session: &Session,
+    caption: &str,
+    severity_bands: bool,
+    findings: &[Finding],
+) {
+    root.fill(&WHITE).unwrap();
+
+    // the chart is drawn over -level, so that loss grows downward
+    let (y_range, y_desc) = match session.scale {
+        Scale::Dbfs => (0.0f64..130.0, "Level [dBFS]"),
+        Scale::HearingLevel => (-120.0..10.0, "Hearing level [dB HL]"),
+    };
+    let levels = session
+        .ears
+        .iter()
+        .flat_map(|ear| ear.points.iter().map(|point| -point.level as f64));
+    let y_range = widen(y_range, levels);
+    let y_lines = ((y_range.end - y_range.start) / 10.0).round() as usize;
+
+    let freqs: Vec<f32> = session
+        .ears
+        .iter()
+        .flat_map(|ear| ear.points.iter().map(|point| point.freq))
+        .collect();
+    let low = freqs.iter().copied().fold(125.0, f32::min) / 2f32.sqrt();
+    let high = freqs.iter().copied().fold(8000.0, f32::max) * 2f32.sqrt();
+    let octaves: Vec<f32> = (-4..8)
+        .map(|n| 125.0 * 2f32.powi(n))
+        .filter(|freq| (low..=high).contains(freq))
+        .collect();
+
+    let mut chart_context = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 15).into_font())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(50)
+        .build_cartesian_2d(
+            (low..high).log_scale().with_key_points(octaves.clone()),
+            y_range.clone(),
+        )
+        .unwrap();
+
+    if severity_bands && session.scale == Scale::HearingLevel {
+        for (i, (lower, name)) in ASHA_DEGREES.iter().enumerate() {
+            let upper = ASHA_DEGREES
+                .get(i + 1)
+                .map(|(next, _)| *next)
+                .unwrap_or(120.0);
+            let shade = if i % 2 == 0 { 0.04 } else { 0.1 };
+            chart_context
+                .draw_series(std::iter::once(Rectangle::new(
+                    [(low, -lower), (high, -upper)],
+                    BLACK.mix(shade).filled(),
+                )))
+                .unwrap();
+            chart_context
+                .draw_series(std::iter::once(Text::new(
+                    name.to_string(),
+                    (low * 1.05, -lower - 1.0),
+                    ("sans-serif", 13).into_font().color(&BLACK.mix(0.6)),
+                )))
+                .unwrap();
+        }
+    }
+
+    // asymmetric frequencies shaded, a quarter octave either side
+    let asymmetries = findings
+        .iter()
+        .filter(|finding| finding.kind == FindingKind::Asymmetry);
+    for (i, finding) in asymmetries.enumerate() {
+        let first = finding.freqs[0] / 2f32.powf(0.25);
+        let last = finding.freqs[finding.freqs.len() - 1] * 2f32.powf(0.25);
+        chart_context
+            .draw_series(std::iter::once(Rectangle::new(
+                [(first, y_range.start), (last, y_range.end)],
+                YELLOW.mix(0.2).filled(),
+            )))
+            .unwrap();
+        let (x, y) = chart_context.backend_coord(&(first, y_range.end));
+        root.draw(&Text::new(
+            format!("{} asymmetry", finding.key),
+            (x + 4, y + 4 + 15 * i as i32),
+            ("sans-serif", 13).into_font(),
+        ))
+        .unwrap();
+    }
+
+    chart_context
+        .configure_mesh()
+        .x_desc("Frequency [Hz]")
+        .y_desc(y_desc)
+        .axis_desc_style(("sans-serif", 15).into_font())
+        .x_label_formatter(&|freq| freq_label(*freq))
+        .y_labels(y_lines + 1)
+        .y_label_formatter(&|y| format!("{}", 0.0 - y))
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .draw()
+        .unwrap();
+    // octave lines, interoctave frequencies dashed, 10 dB lines
+    for freq in &octaves {
+        chart_context
+            .draw_series(series::LineSeries::new(
+                vec![(*freq, y_range.start), (*freq, y_range.end)],
+                BLACK.mix(0.3),
+            ))
+            .unwrap();
+        let half = freq * 1.5;
+        if half < high {
+            chart_context
+                .draw_series(series::DashedLineSeries::new(
+                    vec![(half, y_range.start), (half, y_range.end)],
+                    4,
+                    4,
+                    BLACK.mix(0.2).into(),
+                ))
+                .unwrap();
+        }
+    }
+    for i in 0..=y_lines {
+        let y = y_range.start + 10.0 * i as f64;
+        let style = if y == 0.0 {
+            BLACK.mix(0.8)
+        } else {
+            BLACK.mix(0.2)
+        };
+        chart_context
+            .draw_series(series::LineSeries::new(vec![(low, y), (high, y)], style))
+            .unwrap();
+    }
+
+    for ear in &session.ears {
+        let color = if ear.ear == "R" { RED } else { BLUE };
+        let mut points: Vec<(f32, f64, Symbol, bool)> = ear
+            .points
+            .iter()
+            .map(|point| {
+                let symbol = Symbol::of(&ear.transducer, point.masker_level.is_some());
+                (point.freq, -point.level as f64, symbol, point.no_response)
+            })
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // no-response symbols are not joined to the thresholds
+        let air: Vec<(f32, f64)> = points
+            .iter()
+            .filter(|(_, _, symbol, no_response)| {
+                matches!(symbol, Symbol::Air | Symbol::AirMasked) && !no_response
+            })
+            .map(|(freq, level, _, _)| (*freq, *level))
+            .collect();
+        if ear.ear == "R" {
+            chart_context
+                .draw_series(series::LineSeries::new(air, color.stroke_width(2)))
+                .unwrap();
+        } else {
+            chart_context
+                .draw_series(series::DashedLineSeries::new(
+                    air,
+                    8,
+                    5,
+                    color.stroke_width(2),
+                ))
+                .unwrap();
+        }
+
+        let mut symbols: Vec<Symbol> = Vec::new();
+        for (_, _, symbol, _) in &points {
+            if !symbols.contains(symbol) {
+                symbols.push(*symbol);
+            }
+        }
+        for (freq, level, symbol, no_response) in &points {
+            let at = chart_context.backend_coord(&(*freq, *level));
+            root.draw(&marker(at, *symbol, &ear.ear, color, true))
+                .unwrap();
+            if *no_response {
+                root.draw(&no_response_arrow(at, &ear.ear, color)).unwrap();
+            }
+        }
+        // masked thresholds without a plateau may still be the other ear's
+        for point in ear.points.iter().filter(|point| point.no_plateau) {
+            let (x, y) = chart_context.backend_coord(&(point.freq, -point.level as f64));
+            root.draw(&Text::new(
+                "?",
+                (x + 10, y - 20),
+                ("sans-serif", 16).into_font().color(&color),
+            ))
+            .unwrap();
+        }
+        let notches = findings
+            .iter()
+            .filter(|finding| finding.kind == FindingKind::Notch && finding.key == ear.key());
+        for finding in notches {
+            let level = points
+                .iter()
+                .find(|(freq, _, _, _)| *freq == finding.freqs[0])
+                .map(|(_, level, _, _)| *level)
+                .unwrap();
+            let (x, y) = chart_context.backend_coord(&(finding.freqs[0], level));
+            root.draw(&Text::new(
+                "notch",
+                (x - 15, y + 12),
+                ("sans-serif", 13).into_font().color(&color),
+            ))
+            .unwrap();
+        }
+        for symbol in symbols {
+            let side = ear.ear.clone();
+            chart_context
+                .draw_series(std::iter::empty::<PathElement<(f32, f64)>>())
+                .unwrap()
+                .label(format!("{} {}", ear.key(), symbol.name()))
+                .legend(move |at| marker(at, symbol, &side, color, false));
+        }
+        if points.iter().any(|(_, _, _, no_response)| *no_response) {
+            let side = ear.ear.clone();
+            chart_context
+                .draw_series(std::iter::empty::<PathElement<(f32, f64)>>())
+                .unwrap()
+                .label(format!("{} no response", ear.key()))
+                .legend(move |(x, y)| no_response_arrow((x, y - 12), &side, color));
+        }
+    }
+
+    chart_context
+        .configure_series_labels()
+        .position(SeriesLabelPosition::LowerLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()
+        .unwrap();
+}
+
+/// Octave tick label, e.g. "500" or "2k".
+fn freq_label(freq: f32) -> String {
+    if freq >= 1000.0 {
+        format!("{}k", freq / 1000.0)
+    } else {
+        format!("{}", freq)
+    }
+}
+
+/// `range` widened in 10 dB steps to hold every one of `values`, for
+/// thresholds off the usual scale.
+fn widen(range: Range<f64>, values: impl Iterator<Item = f64>) -> Range<f64> {
+    values.fold(range, |range, y| {
+        range.start.min((y / 10.0).floor() * 10.0)..range.end.max((y / 10.0).ceil() * 10.0)
+    })
+}
+
+/// Coloured line series per transducer and ear, the level magnitude growing
+/// upward.
+fn draw_classic<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    session: &Session,
+    caption: &str,
+) {
+    root.fill(&WHITE).unwrap();
+
+    // dBFS results are attenuations and plotted as their magnitude,
+    // hearing levels are plotted as they are
+    let scale = session.scale;
+    let (y_range, y_desc) = match scale {
+        Scale::Dbfs => (0.0..100.0, "Volume [dB]"),
+        Scale::HearingLevel => (-10.0..120.0, "Hearing level [dB HL]"),
+    };
+    let level = |y: f32| match scale {
+        Scale::Dbfs => y.abs() as f64,
+        Scale::HearingLevel => y as f64,
+    };
+
+    let mut chart_builder = ChartBuilder::on(root);
     let mut chart_context = chart_builder
-        .caption(&filename, ("sans-serif", 15).into_font())
+        .caption(caption, ("sans-serif", 15).into_font())
         .margin(5)
         .x_label_area_size(30)
         .y_label_area_size(30)
-        .build_cartesian_2d((20.0..20000.0).log_scale(), 0.0..100.0)
+        .build_cartesian_2d((20.0..20000.0).log_scale(), y_range)
         .unwrap();
 
     // draw x axis
     chart_context
         .configure_mesh()
         .x_desc("Frequency [Hz]")
-        .y_desc("Volume [dB]")
+        .y_desc(y_desc)
         .axis_desc_style(("sans-serif", 15).into_font())
         .x_labels(10)
         .y_labels(10)
         .draw()
         .unwrap();
 
-    // plot L
-    chart_context
-        .draw_series(series::LineSeries::new(
-            result
-                .get("L")
-                .unwrap()
-                .iter()
-                .map(|(x, y)| (*x as f32, (*y).abs() as f64)),
-            &BLUE,
-        ))
-        .unwrap()
-        .label("L")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &BLUE));
+    // one series per transducer and ear: colour by ear, marker by transducer
+    let mut transducers: Vec<&str> = Vec::new();
+    for ear in &session.ears {
+        if !transducers.contains(&ear.transducer.as_str()) {
+            transducers.push(&ear.transducer);
+        }
+    }
+    for ear in &session.ears {
+        let color = if ear.ear == "R" { RED } else { BLUE };
+        let points: Vec<(f32, f64)> = ear
+            .points
+            .iter()
+            .map(|point| (point.freq, level(point.level)))
+            .collect();
+        let heard: Vec<(f32, f64)> = ear
+            .points
+            .iter()
+            .filter(|point| !point.no_response)
+            .map(|point| (point.freq, level(point.level)))
+            .collect();
+        chart_context
+            .draw_series(series::LineSeries::new(heard, color))
+            .unwrap()
+            .label(ear.key())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        let marker = transducers
+            .iter()
+            .position(|name| *name == ear.transducer)
+            .unwrap();
+        for point in points {
+            match marker % 3 {
+                0 => chart_context
+                    .draw_series(std::iter::once(Circle::new(point, 3, color.filled())))
+                    .unwrap(),
+                1 => chart_context
+                    .draw_series(std::iter::once(TriangleMarker::new(
+                        point,
+                        4,
+                        color.filled(),
+                    )))
+                    .unwrap(),
+                _ => chart_context
+                    .draw_series(std::iter::once(Cross::new(point, 4, color)))
+                    .unwrap(),
+            };
+        }
+        for point in ear.points.iter().filter(|point| point.no_response) {
+            chart_context
+                .draw_series(std::iter::once(Text::new(
+                    "NR",
+                    (point.freq, level(point.level)),
+                    ("sans-serif", 12).into_font().color(&color),
+                )))
+                .unwrap();
+        }
+        for point in ear.points.iter().filter(|point| point.no_plateau) {
+            chart_context
+                .draw_series(std::iter::once(Text::new(
+                    "?",
+                    (point.freq, level(point.level)),
+                    ("sans-serif", 12).into_font().color(&color),
+                )))
+                .unwrap();
+        }
+    }
+
+    // labels
     chart_context
-        .draw_series(
-            result
-                .get("L")
-                .unwrap()
-                .iter()
-                .map(|(x, y)| Circle::new((*x as f32, (*y).abs() as f64), 3, BLUE.filled())),
-        )
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()
         .unwrap();
+}
 
-    // plot R
-    chart_context
-        .draw_series(series::LineSeries::new(
-            result
-                .get("R")
-                .unwrap()
+/// Plots the trials of every threshold into `<date>_staircase.png`: one panel
+/// per transducer, ear (rows) and frequency (columns), with the presented level
+/// over the trial number. Filled markers are heard trials. Does nothing for
+/// sessions without trials.
+pub fn plot_staircase(session: &Session, dir_path: &str) {
+    let rows = session.ears.len();
+    let columns = session
+        .ears
+        .iter()
+        .map(|ear| ear.points.len())
+        .max()
+        .unwrap_or(0);
+    let has_trials = session
+        .ears
+        .iter()
+        .flat_map(|ear| &ear.points)
+        .any(|point| !point.trials.is_empty());
+    if !has_trials {
+        return;
+    }
+    if fs::metadata(dir_path).is_err() {
+        fs::create_dir(dir_path).unwrap();
+    }
+
+    let path_str = format!("{}/{}_staircase.png", dir_path, session.date);
+    let root = BitMapBackend::new(&path_str, (320 * columns as u32, 240 * rows as u32))
+        .into_drawing_area();
+    root.fill(&WHITE).unwrap();
+    let panels = root.split_evenly((rows, columns));
+
+    let scale = session.scale;
+    let (y_range, y_desc) = match scale {
+        Scale::Dbfs => (0.0..100.0, "Volume [dB]"),
+        Scale::HearingLevel => (-10.0..120.0, "Level [dB HL]"),
+    };
+    let level = |y: f32| match scale {
+        Scale::Dbfs => y.abs() as f64,
+        Scale::HearingLevel => y as f64,
+    };
+
+    for (row, ear) in session.ears.iter().enumerate() {
+        let color = if ear.ear == "R" { RED } else { BLUE };
+        for (column, point) in ear.points.iter().enumerate() {
+            let panel = &panels[row * columns + column];
+            let trials = point.trials.len().max(1);
+            let mut chart_context = ChartBuilder::on(panel)
+                .caption(
+                    format!("{} {} Hz", ear.key(), point.freq),
+                    ("sans-serif", 13).into_font(),
+                )
+                .margin(5)
+                .x_label_area_size(35)
+                .y_label_area_size(45)
+                .build_cartesian_2d(0.5..trials as f64 + 0.5, y_range.clone())
+                .unwrap();
+            chart_context
+                .configure_mesh()
+                .x_desc("Trial")
+                .y_desc(y_desc)
+                .x_labels(trials.min(10))
+                .x_label_formatter(&|x| format!("{}", x.round()))
+                .y_labels(5)
+                .draw()
+                .unwrap();
+
+            let steps: Vec<(f64, f64)> = point
+                .trials
                 .iter()
-                .map(|(x, y)| (*x as f32, (*y).abs() as f64)),
-            &RED,
-        ))
-        .unwrap()
-        .label("R")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &RED));
-    chart_context
-        .draw_series(
-            result
-                .get("R")
+                .enumerate()
+                .map(|(i, trial)| ((i + 1) as f64, level(trial.level)))
+                .collect();
+            chart_context
+                .draw_series(series::LineSeries::new(steps.clone(), color))
+                .unwrap();
+            chart_context
+                .draw_series(steps.iter().zip(&point.trials).map(|(step, trial)| {
+                    let style = if trial.response.is_heard() {
+                        color.filled()
+                    } else {
+                        color.stroke_width(1)
+                    };
+                    Circle::new(*step, 3, style)
+                }))
+                .unwrap();
+            // the threshold
+            chart_context
+                .draw_series(series::LineSeries::new(
+                    vec![
+                        (0.5, level(point.level)),
+                        (trials as f64 + 0.5, level(point.level)),
+                    ],
+                    BLACK.mix(0.5),
+                ))
+                .unwrap();
+        }
+    }
+}
+
+/// Plots the sessions of one result directory into `history.png`: per
+/// transducer and ear (rows) the threshold of every frequency over the date,
+/// with significant shifts from the baseline ringed, and the last audiograms
+/// drawn over each other, older ones fainter. Only sessions on the scale of
+/// the latest one are compared. Does nothing for fewer than two sessions.
+pub fn plot_history(sessions: Vec<Session>, dir_path: &str, config: &HistoryConfig) {
+    let sessions = trend::comparable(sessions);
+    if sessions.len() < 2 {
+        return;
+    }
+    let shifts = trend::significant_shifts(&sessions, config.shift);
+    let mut keys: Vec<String> = Vec::new();
+    for ear in sessions.iter().flat_map(|session| &session.ears) {
+        if !keys.contains(&ear.key()) {
+            keys.push(ear.key());
+        }
+    }
+    if fs::metadata(dir_path).is_err() {
+        fs::create_dir(dir_path).unwrap();
+    }
+
+    let path_str = format!("{}/history.png", dir_path);
+    let root = BitMapBackend::new(&path_str, (1400, 420 * keys.len() as u32)).into_drawing_area();
+    root.fill(&WHITE).unwrap();
+    let rows = root.split_evenly((keys.len(), 1));
+
+    // levels are drawn negated so that loss grows downward, as on audiograms
+    let unit = sessions.last().unwrap().scale.unit();
+    let levels: Vec<f64> = sessions
+        .iter()
+        .flat_map(|session| &session.ears)
+        .flat_map(|ear| ear.points.iter().map(|point| -point.level as f64))
+        .collect();
+    let start = levels.first().copied().unwrap_or(0.0);
+    let y_range = widen(start..start, levels.iter().flat_map(|y| [y - 5.0, y + 5.0]));
+    let first = trend::days(&sessions.first().unwrap().date);
+    let last = trend::days(&sessions.last().unwrap().date);
+    let padding = ((last - first) * 0.05).max(1.0);
+
+    for (key, row) in keys.iter().zip(&rows) {
+        let (left, right) = row.split_horizontally(700);
+        let mut freqs: Vec<f32> = sessions
+            .iter()
+            .flat_map(|session| &session.ears)
+            .filter(|ear| ear.key() == *key)
+            .flat_map(|ear| ear.points.iter().map(|point| point.freq))
+            .collect();
+        freqs.sort_by(f32::total_cmp);
+        freqs.dedup();
+
+        // thresholds over time
+        let mut chart_context = ChartBuilder::on(&left)
+            .caption(format!("{} over time", key), ("sans-serif", 15).into_font())
+            .margin(10)
+            .x_label_area_size(35)
+            .y_label_area_size(50)
+            .build_cartesian_2d(first - padding..last + padding, y_range.clone())
+            .unwrap();
+        chart_context
+            .configure_mesh()
+            .y_desc(format!("Threshold [{}]", unit))
+            .x_labels(6)
+            .x_label_formatter(&|x| trend::day_label(*x))
+            .y_label_formatter(&|y| format!("{}", 0.0 - y))
+            .draw()
+            .unwrap();
+        for (i, freq) in freqs.iter().enumerate() {
+            let color = Palette99::pick(i).to_rgba();
+            let points: Vec<(f64, f64)> = sessions
+                .iter()
+                .filter_map(|session| {
+                    let ear = session.ears.iter().find(|ear| ear.key() == *key)?;
+                    let point = ear.points.iter().find(|point| point.freq == *freq)?;
+                    Some((trend::days(&session.date), -point.level as f64))
+                })
+                .collect();
+            chart_context
+                .draw_series(series::LineSeries::new(
+                    points.clone(),
+                    color.stroke_width(2),
+                ))
                 .unwrap()
+                .label(format!("{} Hz", freq))
+                .legend(move |(x, y)| {
+                    PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+                });
+            chart_context
+                .draw_series(
+                    points
+                        .iter()
+                        .map(|point| Circle::new(*point, 3, color.filled())),
+                )
+                .unwrap();
+        }
+        let ringed: Vec<(f64, f64)> = shifts
+            .iter()
+            .filter(|shift| shift.key == *key)
+            .map(|shift| {
+                let level = sessions
+                    .iter()
+                    .find(|session| session.date == shift.date)
+                    .and_then(|session| session.ears.iter().find(|ear| ear.key() == *key))
+                    .and_then(|ear| ear.points.iter().find(|point| point.freq == shift.freq))
+                    .unwrap()
+                    .level;
+                (trend::days(&shift.date), -level as f64)
+            })
+            .collect();
+        if !ringed.is_empty() {
+            chart_context
+                .draw_series(
+                    ringed
+                        .into_iter()
+                        .map(|point| Circle::new(point, 8, BLACK.stroke_width(2))),
+                )
+                .unwrap()
+                .label(format!("shift of {} dB or more", config.shift))
+                .legend(|at| Circle::new(at, 6, BLACK.stroke_width(2)));
+        }
+        chart_context
+            .configure_series_labels()
+            .position(SeriesLabelPosition::LowerLeft)
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()
+            .unwrap();
+
+        // the last audiograms, the newest in full colour
+        let recent: Vec<&Session> = sessions
+            .iter()
+            .filter(|session| session.ears.iter().any(|ear| ear.key() == *key))
+            .collect();
+        let recent = &recent[recent.len().saturating_sub(config.overlay.max(1))..];
+        let low = freqs.iter().copied().fold(125.0, f32::min) / 2f32.sqrt();
+        let high = freqs.iter().copied().fold(8000.0, f32::max) * 2f32.sqrt();
+        let octaves: Vec<f32> = (-4..8)
+            .map(|n| 125.0 * 2f32.powi(n))
+            .filter(|freq| (low..=high).contains(freq))
+            .collect();
+        let mut chart_context = ChartBuilder::on(&right)
+            .caption(
+                format!("{}, last {} audiograms", key, recent.len()),
+                ("sans-serif", 15).into_font(),
+            )
+            .margin(10)
+            .x_label_area_size(35)
+            .y_label_area_size(50)
+            .build_cartesian_2d(
+                (low..high).log_scale().with_key_points(octaves),
+                y_range.clone(),
+            )
+            .unwrap();
+        chart_context
+            .configure_mesh()
+            .x_desc("Frequency [Hz]")
+            .y_desc(format!("Threshold [{}]", unit))
+            .x_label_formatter(&|freq| freq_label(*freq))
+            .y_label_formatter(&|y| format!("{}", 0.0 - y))
+            .draw()
+            .unwrap();
+        let base = if key.ends_with('R') { RED } else { BLUE };
+        for (i, session) in recent.iter().enumerate() {
+            let newest = i + 1 == recent.len();
+            let color = base.mix(0.15 + 0.85 * (i + 1) as f64 / recent.len() as f64);
+            let width = if newest { 3 } else { 1 };
+            let ear = session.ears.iter().find(|ear| ear.key() == *key).unwrap();
+            let mut points: Vec<(f32, f64)> = ear
+                .points
                 .iter()
-                .map(|(x, y)| Circle::new((*x as f32, (*y).abs() as f64), 3, RED.filled())),
-        )
-        .unwrap();
+                .map(|point| (point.freq, -point.level as f64))
+                .collect();
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+            chart_context
+                .draw_series(series::LineSeries::new(
+                    points.clone(),
+                    color.stroke_width(width),
+                ))
+                .unwrap()
+                .label(trend::day_label(trend::days(&session.date)))
+                .legend(move |(x, y)| {
+                    PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(width))
+                });
+            chart_context
+                .draw_series(
+                    points
+                        .into_iter()
+                        .map(|point| Circle::new(point, 3, color.filled())),
+                )
+                .unwrap();
+        }
+        chart_context
+            .configure_series_labels()
+            .position(SeriesLabelPosition::LowerLeft)
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()
+            .unwrap();
+    }
+}
 
-    // labels
-    chart_context
-        .configure_series_labels()
-        .background_style(&WHITE.mix(0.8))
-        .border_style(&BLACK)
-        .draw()
-        .unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{EarResult, ThresholdPoint};
+    use plotters::element::PointCollection;
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(Symbol::of("headphone", false), Symbol::Air);
+        assert_eq!(Symbol::of("insert", true), Symbol::AirMasked);
+        assert_eq!(Symbol::of("bone", true), Symbol::BoneMasked);
+        assert_eq!(Symbol::of("speaker", false), Symbol::SoundField);
+
+        // right-ear bone conduction "<" beside the frequency line, opening right
+        let path = marker((100, 100), Symbol::Bone, "R", RED, true);
+        let points = (&path).point_iter().to_vec();
+        assert_eq!(points, [(-5 + 100, 93), (-13 + 100, 100), (-5 + 100, 107)]);
+        // legend entries stay centred
+        let path = marker((0, 0), Symbol::Bone, "L", BLUE, false);
+        let points = (&path).point_iter().to_vec();
+        assert_eq!(points, [(-4, -7), (4, 0), (-4, 7)]);
+
+        let config: PlotConfig = serde_yaml::from_str("{severity_bands: true}").unwrap();
+        assert_eq!(config.style, AudiogramStyle::Clinical);
+    }
+
+    #[test]
+    fn test_report_table() {
+        let point = |freq, level, masker_level| ThresholdPoint {
+            masker_level,
+            ..ThresholdPoint::new(freq, level)
+        };
+        let mut session = Session::new("20240101_120000");
+        let mut right = EarResult::new("headphone", "R");
+        right.points = vec![point(4000.0, 35.0, None), point(1000.0, 10.0, None)];
+        let mut left = EarResult::new("bone", "L");
+        left.points = vec![
+            point(1000.0, 5.5, Some(-40.0)),
+            point(4000.0, 20.0, Some(-30.0)),
+        ];
+        left.points[1].no_plateau = true;
+        right.points[0].no_response = true;
+        session.ears = vec![right, left];
+
+        let (freqs, rows) = threshold_table(&session);
+        assert_eq!(freqs, [1000.0, 4000.0]);
+        assert_eq!(
+            rows,
+            [
+                (
+                    "headphone:R".to_string(),
+                    vec!["10.0".to_string(), "35.0 nr".to_string()]
+                ),
+                (
+                    "bone:L".to_string(),
+                    vec!["5.5 m".to_string(), "20.0 m?".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(
+            calibration_lines(&session),
+            ["uncalibrated, levels are in dBFS"]
+        );
+        session.calibration_fingerprint = Some("5f0d6b2e1c4a9f83".to_string());
+        assert_eq!(
+            calibration_lines(&session),
+            ["Calibration 5f0d6b2e1c4a9f83, tables not recorded"]
+        );
+    }
 }
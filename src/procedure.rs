@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hughson_westlake::HughsonWestlake;
+
+/// A listener's answer to the stimulus currently presented.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    /// The tone was heard (yes/no procedures).
+    Heard,
+    /// The tone was not heard within the response window (yes/no procedures).
+    NotHeard,
+    /// Make the tone louder (adjustment procedures).
+    Up,
+    /// Make the tone quieter (adjustment procedures).
+    Down,
+    /// Accept the current level (adjustment procedures).
+    Confirm,
+    /// Restart the current frequency from the start level.
+    Reset,
+}
+
+impl Response {
+    /// Whether the answer means the stimulus was audible.
+    pub fn is_heard(&self) -> bool {
+        matches!(self, Response::Heard | Response::Down)
+    }
+}
+
+/// What a procedure wants to happen after a response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// Present the stimulus at this level in dBFS.
+    Present(f64),
+    /// The threshold in dBFS has been found.
+    Threshold(f64),
+    /// The stimulus was not heard at the highest level allowed, given in dBFS.
+    NoResponse(f64),
+}
+
+/// How the listener answers a procedure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseKind {
+    /// The stimulus plays continuously while the listener moves the level.
+    Adjust,
+    /// Each stimulus is presented once and the listener says whether it was heard.
+    YesNo,
+}
+
+/// Threshold-seeking logic for a single frequency on a single ear.
+///
+/// A procedure knows nothing about terminals or audio devices: the caller presents
+/// the level it asks for, feeds back the listener's `Response` and stops once a
+/// `Step::Threshold` or `Step::NoResponse` is returned.
+pub trait TestProcedure {
+    fn response_kind(&self) -> ResponseKind;
+
+    /// Level of the first presentation in dBFS.
+    fn start_level(&self) -> f64;
+
+    fn respond(&mut self, response: Response) -> Step;
+}
+
+/// Levels and step sizes of the procedures, the `levels` section of
+/// `config.yaml`. All in dB, levels in dBFS.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Levels {
+    /// Level of the first presentation of every frequency.
+    #[serde(default = "default_start")]
+    pub start: f64,
+    /// `manual`: change per up/down key press.
+    #[serde(default = "default_step")]
+    pub step: f64,
+    /// `hughson_westlake`: decrease after a heard tone.
+    #[serde(default = "default_step_down")]
+    pub step_down: f64,
+    /// `hughson_westlake`: increase after a missed tone.
+    #[serde(default = "default_step_up")]
+    pub step_up: f64,
+    /// `hughson_westlake`: quietest level presented.
+    #[serde(default = "default_min")]
+    pub min: f64,
+    /// `hughson_westlake`: loudest level presented.
+    #[serde(default = "default_max")]
+    pub max: f64,
+}
+
+fn default_start() -> f64 {
+    -24.0
+}
+
+fn default_step() -> f64 {
+    2.0
+}
+
+fn default_step_down() -> f64 {
+    10.0
+}
+
+fn default_step_up() -> f64 {
+    5.0
+}
+
+fn default_min() -> f64 {
+    -120.0
+}
+
+fn default_max() -> f64 {
+    0.0
+}
+
+impl Default for Levels {
+    fn default() -> Levels {
+        Levels {
+            start: default_start(),
+            step: default_step(),
+            step_down: default_step_down(),
+            step_up: default_step_up(),
+            min: default_min(),
+            max: default_max(),
+        }
+    }
+}
+
+/// Procedure selected in `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Procedure {
+    /// The listener walks the volume down with the arrow keys and presses Enter.
+    #[default]
+    Manual,
+    /// Automatic "down 10, up 5" search with yes/no responses.
+    HughsonWestlake,
+}
+
+impl Procedure {
+    /// Builds a fresh procedure for one frequency.
+    pub fn build(&self, levels: &Levels) -> Box<dyn TestProcedure> {
+        match self {
+            Procedure::Manual => Box::new(Manual::new(levels.start, levels.step)),
+            Procedure::HughsonWestlake => Box::new(HughsonWestlake::new(levels)),
+        }
+    }
+}
+
+/// Method of adjustment: the level follows the listener's up/down presses and the
+/// threshold is the level at which the listener confirms.
+#[derive(Clone, Debug)]
+pub struct Manual {
+    start_level: f64,
+    step: f64,
+    level: f64,
+}
+
+impl Manual {
+    pub fn new(start_level: f64, step: f64) -> Manual {
+        Manual {
+            start_level,
+            step,
+            level: start_level,
+        }
+    }
+}
+
+impl TestProcedure for Manual {
+    fn response_kind(&self) -> ResponseKind {
+        ResponseKind::Adjust
+    }
+
+    fn start_level(&self) -> f64 {
+        self.start_level
+    }
+
+    fn respond(&mut self, response: Response) -> Step {
+        match response {
+            Response::Up => self.level += self.step,
+            Response::Down => self.level -= self.step,
+            Response::Reset => self.level = self.start_level,
+            Response::Confirm => return Step::Threshold(self.level),
+            Response::Heard | Response::NotHeard => {}
+        }
+        Step::Present(self.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_follows_keys() {
+        let mut procedure = Manual::new(-24.0, 2.0);
+        assert_eq!(procedure.respond(Response::Down), Step::Present(-26.0));
+        assert_eq!(procedure.respond(Response::Down), Step::Present(-28.0));
+        assert_eq!(procedure.respond(Response::Up), Step::Present(-26.0));
+        assert_eq!(procedure.respond(Response::Confirm), Step::Threshold(-26.0));
+    }
+
+    #[test]
+    fn test_manual_reset() {
+        let mut procedure = Manual::new(-24.0, 2.0);
+        procedure.respond(Response::Down);
+        assert_eq!(procedure.respond(Response::Reset), Step::Present(-24.0));
+    }
+
+    #[test]
+    fn test_procedure_from_yaml() {
+        let procedure: Procedure = serde_yaml::from_str("hughson_westlake").unwrap();
+        assert_eq!(procedure, Procedure::HughsonWestlake);
+        let levels = Levels::default();
+        assert_eq!(
+            procedure.build(&levels).response_kind(),
+            ResponseKind::YesNo
+        );
+        assert_eq!(Procedure::default().build(&levels).start_level(), -24.0);
+
+        let levels: Levels = serde_yaml::from_str("{start: -40.0, step: 1.0}").unwrap();
+        assert_eq!(levels.step_down, 10.0);
+        let mut manual = Procedure::Manual.build(&levels);
+        assert_eq!(manual.start_level(), -40.0);
+        assert_eq!(manual.respond(Response::Up), Step::Present(-39.0));
+    }
+}
@@ -0,0 +1,478 @@
+use std::time::Duration;
+
+use crate::channel_map::{channel_count, other_ear, Transducer};
+use crate::listener::{Listener, Presentation};
+use crate::masking::{Masker, MaskingConfig};
+use crate::model::{EarResult, ThresholdPoint, Trial};
+use crate::output::AudioOutput;
+use crate::procedure::{Response, ResponseKind, Step, TestProcedure};
+use crate::stimulus::Stimulus;
+use crate::Point;
+
+/// Runs the whole test: every transducer of `config.channel_map`, L then R,
+/// over every `config.test_freq`. Returns the thresholds in dBFS.
+///
+/// With `config.masking` and a criterion, thresholds that are much worse than
+/// the other ear are measured again with the masker afterwards.
+pub fn run_session(
+    config: &Point,
+    output: &mut dyn AudioOutput,
+    listener: &mut dyn Listener,
+) -> Vec<EarResult> {
+    let mut ears = Vec::new();
+    let mask_all = matches!(&config.masking, Some(masking) if masking.criterion.is_none());
+    let channels = channel_count(&config.channel_map);
+
+    // iterate over the test_freq
+    for transducer in &config.channel_map {
+        for ear in &config.ear_order.arrange(&config.ears) {
+            let mut result = EarResult::new(&transducer.transducer, ear);
+            for freq in &config.test_freq {
+                let stimulus = Stimulus::new(
+                    config.stimulus,
+                    *freq,
+                    transducer.channel_of(ear),
+                    channels,
+                    config.timing,
+                );
+                let point = match &config.masking {
+                    Some(masking) if mask_all => measure_masked(
+                        config, masking, output, listener, transducer, ear, &stimulus,
+                    ),
+                    _ => measure_threshold(
+                        config,
+                        output,
+                        listener,
+                        &transducer.transducer,
+                        ear,
+                        &stimulus,
+                    ),
+                };
+                result.points.push(point);
+            }
+            ears.push(result);
+        }
+    }
+
+    // re-test where the other ear may have heard the tone
+    if let Some(masking) = &config.masking {
+        if let Some(criterion) = masking.criterion {
+            for transducer in &config.channel_map {
+                for ear in &config.ears {
+                    let position = |ear: &str| {
+                        ears.iter().position(|result: &EarResult| {
+                            result.transducer == transducer.transducer && result.ear == ear
+                        })
+                    };
+                    // nothing to compare with when only one ear is tested
+                    let (Some(index), Some(other_index)) =
+                        (position(ear), position(other_ear(ear)))
+                    else {
+                        continue;
+                    };
+                    for (i, freq) in config.test_freq.iter().enumerate() {
+                        let threshold = ears[index].points[i].level;
+                        let other_threshold = ears[other_index].points[i].level;
+                        if ((threshold - other_threshold) as f64) < criterion {
+                            continue;
+                        }
+                        let stimulus = Stimulus::new(
+                            config.stimulus,
+                            *freq,
+                            transducer.channel_of(ear),
+                            channels,
+                            config.timing,
+                        );
+                        ears[index].points[i] = measure_masked(
+                            config, masking, output, listener, transducer, ear, &stimulus,
+                        );
+                    }
+                }
+            }
+        }
+    }
+    ears
+}
+
+/// Measures one threshold with a fresh procedure.
+fn measure_threshold(
+    config: &Point,
+    output: &mut dyn AudioOutput,
+    listener: &mut dyn Listener,
+    transducer: &str,
+    ear: &str,
+    stimulus: &Stimulus,
+) -> ThresholdPoint {
+    let mut procedure = config.procedure.build(&config.levels);
+    listener.begin(procedure.response_kind(), transducer, ear, stimulus);
+    let presentation = Presentation {
+        transducer,
+        ear,
+        stimulus,
+        level: procedure.start_level(),
+    };
+    let point = measure(output, listener, procedure.as_mut(), presentation);
+    listener.finish(transducer, ear, stimulus, point.level as f64);
+
+    let pause = Duration::from_secs_f32(stimulus.timing.pause);
+    listener.pause(pause);
+    output.advance(pause);
+    point
+}
+
+/// Measures a threshold with the masker in the other ear.
+///
+/// With a plateau configured, the masker is raised step by step and the
+/// threshold re-measured. A threshold that moves with the masker means the
+/// other ear was still hearing the tone, so the plateau restarts there; it is
+/// accepted once it has stayed put for `plateau.width` steps. If the masker
+/// would pass `plateau.max_level` first, the threshold measured with the
+/// loudest masker is kept and flagged `no_plateau`. The trials and response
+/// counts add up over all measurements.
+fn measure_masked(
+    config: &Point,
+    masking: &MaskingConfig,
+    output: &mut dyn AudioOutput,
+    listener: &mut dyn Listener,
+    transducer: &Transducer,
+    ear: &str,
+    stimulus: &Stimulus,
+) -> ThresholdPoint {
+    let mut masker = Masker {
+        level: masking.effective_level,
+        seed: masking.seed,
+        channel: masking
+            .transducer(&config.channel_map, transducer)
+            .unwrap_or_else(|message| panic!("masking: {}", message))
+            .channel_of(other_ear(ear)),
+    };
+    let name = &transducer.transducer;
+    let mut start = measure_threshold(
+        config,
+        output,
+        listener,
+        name,
+        ear,
+        &stimulus.masked(masker),
+    );
+    start.masker_level = Some(masker.level as f32);
+    let Some(plateau) = &masking.plateau else {
+        return start;
+    };
+
+    let mut trials = start.trials.clone();
+    let mut last = start.clone();
+    let mut stable = 0;
+    while stable < plateau.width {
+        masker.level += plateau.step;
+        if masker.level > plateau.max_level {
+            start = last;
+            start.no_plateau = true;
+            break;
+        }
+        let mut point = measure_threshold(
+            config,
+            output,
+            listener,
+            name,
+            ear,
+            &stimulus.masked(masker),
+        );
+        point.masker_level = Some(masker.level as f32);
+        trials.extend_from_slice(&point.trials);
+        if ((point.level - start.level) as f64).abs() <= plateau.tolerance {
+            stable += 1;
+        } else {
+            start = point.clone();
+            stable = 0;
+        }
+        last = point;
+    }
+    start.presentations = trials.len() as u32;
+    start.heard = trials
+        .iter()
+        .filter(|trial| trial.response.is_heard())
+        .count() as u32;
+    start.trials = trials;
+    start
+}
+
+/// Presents the tone to the listener until `procedure` settles on a threshold.
+/// Returns the threshold in dBFS with every trial that led to it, or the
+/// highest level allowed when the listener did not respond there.
+fn measure(
+    output: &mut dyn AudioOutput,
+    listener: &mut dyn Listener,
+    procedure: &mut dyn TestProcedure,
+    mut presentation: Presentation,
+) -> ThresholdPoint {
+    let kind = procedure.response_kind();
+    let stimulus = presentation.stimulus;
+    let masker_level = stimulus.masker.map(|masker| masker.level as f32);
+    let mut trials = Vec::new();
+    output.play(stimulus, presentation.level);
+    let mut onset = output.elapsed();
+    loop {
+        let (response, elapsed) = listener.respond(kind, &presentation);
+        output.advance(elapsed);
+        trials.push(Trial {
+            onset: onset.as_secs_f64(),
+            level: presentation.level as f32,
+            masker_level,
+            response,
+            reaction_time: elapsed.as_secs_f64(),
+        });
+
+        let step = procedure.respond(response);
+        match step {
+            Step::Threshold(threshold) | Step::NoResponse(threshold) => {
+                output.stop();
+                return ThresholdPoint {
+                    presentations: trials.len() as u32,
+                    heard: trials
+                        .iter()
+                        .filter(|trial| trial.response.is_heard())
+                        .count() as u32,
+                    trials,
+                    no_response: matches!(step, Step::NoResponse(_)),
+                    ..ThresholdPoint::new(stimulus.freq, threshold as f32)
+                };
+            }
+            Step::Present(level) => {
+                presentation.level = level;
+                match kind {
+                    ResponseKind::Adjust if response == Response::Reset => {
+                        output.play(stimulus, level);
+                    }
+                    ResponseKind::Adjust => output.set_level(level),
+                    ResponseKind::YesNo => {
+                        output.stop();
+                        let pause = Duration::from_secs_f32(stimulus.timing.pause);
+                        listener.pause(pause);
+                        output.advance(pause);
+                        output.play(stimulus, level);
+                    }
+                }
+                onset = output.elapsed();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listener::ListenerConfig;
+    use crate::simulated::SimulatedListener;
+
+    /// Output that plays nothing, for tests without a sound card.
+    #[derive(Default)]
+    struct Silent {
+        elapsed: Duration,
+    }
+
+    impl AudioOutput for Silent {
+        fn play(&mut self, _stimulus: &Stimulus, _level: f64) {}
+        fn set_level(&mut self, _level: f64) {}
+        fn stop(&mut self) {}
+        fn advance(&mut self, duration: Duration) {
+            self.elapsed += duration;
+        }
+        fn elapsed(&self) -> Duration {
+            self.elapsed
+        }
+    }
+
+    const CONFIG: &str = "
+memo: simulated
+test_freq: [250, 500, 1000, 2000, 4000, 6000]
+listener:
+  kind: simulated
+  slope: 2.0
+  false_positive_rate: 0.0
+  seed: 42
+  audiogram:
+    L: {250: -70.0, 1000: -62.0, 4000: -45.0, 8000: -40.0}
+    R: {250: -75.0, 1000: -72.0, 4000: -68.0, 8000: -50.0}
+";
+
+    fn assert_recovers(procedure: &str, tolerance: f64) {
+        let config: Point =
+            serde_yaml::from_str(&format!("{}procedure: {}\n", CONFIG, procedure)).unwrap();
+        let mut listener = config.listener.open(config.timing);
+        let ears = run_session(&config, &mut Silent::default(), listener.as_mut());
+
+        let ListenerConfig::Simulated { audiogram, .. } = &config.listener else {
+            unreachable!()
+        };
+        let reference = SimulatedListener::new(audiogram.clone(), 2.0, 0.0, None, config.timing, 0);
+        assert_eq!(ears.len(), 2);
+        for result in &ears {
+            assert_eq!(result.points.len(), config.test_freq.len());
+            for point in &result.points {
+                let expected = reference.threshold(&result.transducer, &result.ear, point.freq);
+                assert!(
+                    (point.level as f64 - expected).abs() <= tolerance,
+                    "{} {} Hz: got {}, expected {}",
+                    result.key(),
+                    point.freq,
+                    point.level,
+                    expected
+                );
+                assert!(point.presentations > 0);
+                assert!(point.heard <= point.presentations);
+            }
+        }
+    }
+
+    #[test]
+    fn test_manual_recovers_simulated_thresholds() {
+        assert_recovers("manual", 5.0);
+    }
+
+    #[test]
+    fn test_hughson_westlake_recovers_simulated_thresholds() {
+        assert_recovers("hughson_westlake", 5.0);
+    }
+
+    const ASYMMETRIC: &str = "
+memo: simulated, right ear 50 dB worse
+test_freq: [1000, 4000]
+procedure: hughson_westlake
+listener:
+  kind: simulated
+  slope: 2.0
+  false_positive_rate: 0.0
+  interaural_attenuation: 40.0
+  seed: 7
+  audiogram:
+    L: {1000: -80.0}
+    R: {1000: -30.0}
+";
+
+    fn find<'a>(ears: &'a [EarResult], transducer: &str, ear: &str) -> &'a EarResult {
+        ears.iter()
+            .find(|result| result.transducer == transducer && result.ear == ear)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cross_hearing_without_masking() {
+        let config: Point = serde_yaml::from_str(ASYMMETRIC).unwrap();
+        let mut listener = config.listener.open(config.timing);
+        let ears = run_session(&config, &mut Silent::default(), listener.as_mut());
+
+        // the left ear answers for the right one, 40 dB below the left threshold
+        for point in &find(&ears, "headphone", "R").points {
+            assert!((point.level + 40.0).abs() <= 5.0, "got {}", point.level);
+        }
+        assert!(ears
+            .iter()
+            .flat_map(|result| &result.points)
+            .all(|point| point.masker_level.is_none()));
+    }
+
+    #[test]
+    fn test_plateau_masking_recovers_worse_ear() {
+        let config: Point = serde_yaml::from_str(&format!(
+            "{}{}",
+            ASYMMETRIC,
+            "
+masking:
+  effective_level: -60.0
+  criterion: 20.0
+  plateau: {step: 5.0, width: 3, tolerance: 5.0, max_level: -20.0}
+"
+        ))
+        .unwrap();
+        let mut listener = config.listener.open(config.timing);
+        let ears = run_session(&config, &mut Silent::default(), listener.as_mut());
+
+        for point in &find(&ears, "headphone", "R").points {
+            assert!(
+                (point.level + 30.0).abs() <= 5.0,
+                "{} Hz: got {}",
+                point.freq,
+                point.level
+            );
+            let masker_level = point.masker_level.unwrap();
+            assert!((-60.0..=-20.0).contains(&masker_level));
+            assert!(!point.no_plateau);
+        }
+        for point in &find(&ears, "headphone", "L").points {
+            assert!((point.level + 80.0).abs() <= 5.0, "got {}", point.level);
+            assert_eq!(point.masker_level, None);
+        }
+
+        // two steps up to max_level are too few for a plateau of three
+        let config: Point = serde_yaml::from_str(&format!(
+            "{}{}",
+            ASYMMETRIC,
+            "
+masking:
+  effective_level: -60.0
+  criterion: 20.0
+  plateau: {step: 5.0, width: 3, tolerance: 5.0, max_level: -50.0}
+"
+        ))
+        .unwrap();
+        let mut listener = config.listener.open(config.timing);
+        let ears = run_session(&config, &mut Silent::default(), listener.as_mut());
+        for point in &find(&ears, "headphone", "R").points {
+            assert!(point.no_plateau);
+            assert_eq!(point.masker_level, Some(-50.0));
+        }
+    }
+
+    #[test]
+    fn test_results_keyed_by_transducer_and_ear() {
+        let config: Point = serde_yaml::from_str(&format!(
+            "{}{}",
+            CONFIG,
+            "
+procedure: hughson_westlake
+channel_map:
+  - {transducer: headphone, channel: 0}
+  - {transducer: bone, channel: 2, channels: 1}
+"
+        ))
+        .unwrap();
+        let mut listener = config.listener.open(config.timing);
+        let ears = run_session(&config, &mut Silent::default(), listener.as_mut());
+        let keys: Vec<String> = ears.iter().map(EarResult::key).collect();
+        assert_eq!(keys, ["headphone:L", "headphone:R", "bone:L", "bone:R"]);
+    }
+
+    #[test]
+    fn test_trials_reconstruct_staircase() {
+        let config: Point =
+            serde_yaml::from_str(&format!("{}procedure: hughson_westlake\n", CONFIG)).unwrap();
+        let mut listener = config.listener.open(config.timing);
+        let ears = run_session(&config, &mut Silent::default(), listener.as_mut());
+
+        let window = config.timing.response_window().as_secs_f64();
+        let mut last_onset = -1.0;
+        for point in ears.iter().flat_map(|ear| &ear.points) {
+            assert_eq!(point.presentations as usize, point.trials.len());
+            assert_eq!(point.trials[0].level, -24.0);
+            for pair in point.trials.windows(2) {
+                // down 10 after a heard tone, up 5 after a missed one
+                let step = if pair[0].response.is_heard() {
+                    -10.0
+                } else {
+                    5.0
+                };
+                assert_eq!(pair[1].level, (pair[0].level + step).clamp(-120.0, 0.0));
+            }
+            for trial in &point.trials {
+                assert!(trial.onset > last_onset);
+                last_onset = trial.onset;
+                // the simulated listener answers within the response window
+                assert!(trial.reaction_time > 0.0 && trial.reaction_time <= window);
+            }
+            let last = point.trials.last().unwrap();
+            assert_eq!(last.level, point.level);
+            assert!(last.response.is_heard());
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::calibration::interpolate;
+use crate::channel_map::other_ear;
+use crate::gate::Timing;
+use crate::listener::{Listener, Presentation};
+use crate::procedure::{Response, ResponseKind};
+
+/// A virtual participant answering from a known audiogram.
+///
+/// The probability of hearing a tone follows a logistic psychometric function
+/// centred on the audiogram threshold, plus a fixed false-positive rate:
+/// `p = fp + (1 - fp) / (1 + exp(-slope * (level - threshold)))`.
+///
+/// With an interaural attenuation the other ear hears the tone that much
+/// softer (cross-hearing) unless a masker covers it, and a loud masker
+/// crosses over to raise the threshold of the test ear (overmasking).
+///
+/// Heard tones are answered at the end of the first burst, missed ones at the
+/// end of the response window, both taken from `timing`.
+pub struct SimulatedListener {
+    audiogram: BTreeMap<String, BTreeMap<i32, f64>>,
+    slope: f64,
+    false_positive_rate: f64,
+    interaural_attenuation: Option<f64>,
+    timing: Timing,
+    rng: StdRng,
+}
+
+impl SimulatedListener {
+    pub fn new(
+        audiogram: BTreeMap<String, BTreeMap<i32, f64>>,
+        slope: f64,
+        false_positive_rate: f64,
+        interaural_attenuation: Option<f64>,
+        timing: Timing,
+        seed: u64,
+    ) -> SimulatedListener {
+        SimulatedListener {
+            audiogram,
+            slope,
+            false_positive_rate,
+            interaural_attenuation,
+            timing,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Threshold in dBFS at `freq`, interpolated over log frequency between
+    /// audiogram points. An audiogram for "<transducer>:<ear>" takes
+    /// precedence over the one for the ear alone. `validate::check` makes sure
+    /// one of them exists for every tested transducer and ear.
+    pub fn threshold(&self, transducer: &str, ear: &str, freq: f32) -> f64 {
+        let audiogram = self
+            .audiogram
+            .get(&format!("{}:{}", transducer, ear))
+            .or_else(|| self.audiogram.get(ear))
+            .unwrap_or_else(|| panic!("the audiogram has no thresholds for {}", ear));
+        interpolate(audiogram, freq)
+    }
+
+    /// Probability of hearing `presentation`.
+    pub fn probability(&self, presentation: &Presentation) -> f64 {
+        let freq = presentation.stimulus.freq;
+        let transducer = presentation.transducer;
+        let threshold = self.threshold(transducer, presentation.ear, freq);
+        let p = match self.interaural_attenuation {
+            None => self.logistic(presentation.level - threshold),
+            Some(attenuation) => {
+                let mut threshold = threshold;
+                let mut other_threshold =
+                    self.threshold(transducer, other_ear(presentation.ear), freq);
+                if let Some(masker) = presentation.stimulus.masker {
+                    threshold = threshold.max(masker.level - attenuation);
+                    other_threshold = other_threshold.max(masker.level);
+                }
+                let p_test = self.logistic(presentation.level - threshold);
+                let p_other = self.logistic(presentation.level - attenuation - other_threshold);
+                1.0 - (1.0 - p_test) * (1.0 - p_other)
+            }
+        };
+        self.false_positive_rate + (1.0 - self.false_positive_rate) * p
+    }
+
+    fn logistic(&self, above_threshold: f64) -> f64 {
+        1.0 / (1.0 + (-self.slope * above_threshold).exp())
+    }
+}
+
+impl Listener for SimulatedListener {
+    fn respond(&mut self, kind: ResponseKind, presentation: &Presentation) -> (Response, Duration) {
+        let heard = self.rng.gen::<f64>() < self.probability(presentation);
+        let burst = Duration::from_secs_f32(self.timing.on);
+        let cycle = Duration::from_secs_f32(self.timing.on + self.timing.off);
+        match kind {
+            // walk the volume down until the tone disappears, as instructed
+            ResponseKind::Adjust if heard => (Response::Down, cycle),
+            ResponseKind::Adjust => (Response::Confirm, cycle),
+            ResponseKind::YesNo if heard => (Response::Heard, burst),
+            ResponseKind::YesNo => (Response::NotHeard, self.timing.response_window()),
+        }
+    }
+}
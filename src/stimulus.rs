@@ -0,0 +1,179 @@
+use rodio::source::Source;
+use serde::{Deserialize, Serialize};
+
+use crate::gate::Timing;
+use crate::masking::{MaskedTone, Masker, MaskerGain};
+use crate::noise::{Noise, NoiseBand};
+use crate::sinewave::SineWave;
+use crate::warble::WarbleTone;
+
+/// Signal used for the test tones, selected in `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum StimulusKind {
+    /// Pure tone.
+    #[default]
+    Sine,
+    /// Frequency-modulated tone, for free-field speakers and standing waves.
+    Warble {
+        /// Modulation rate in Hz.
+        #[serde(default = "default_warble_rate")]
+        rate: f32,
+        /// Peak frequency deviation as a fraction of the test frequency.
+        #[serde(default = "default_warble_depth")]
+        depth: f32,
+    },
+    /// Broadband white noise.
+    WhiteNoise {
+        #[serde(default)]
+        seed: u64,
+    },
+    /// Broadband pink noise.
+    PinkNoise {
+        #[serde(default)]
+        seed: u64,
+    },
+    /// 1/3-octave noise band centred on the test frequency.
+    NarrowbandNoise {
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+impl StimulusKind {
+    /// What is played, for the instructions, e.g. "a warble tone (±5 % at 5 Hz)".
+    pub fn describe(&self) -> String {
+        match self {
+            StimulusKind::Sine => "a sine tone".to_string(),
+            StimulusKind::Warble { rate, depth } => {
+                format!("a warble tone (±{} % at {} Hz)", depth * 100.0, rate)
+            }
+            StimulusKind::WhiteNoise { .. } => "white noise".to_string(),
+            StimulusKind::PinkNoise { .. } => "pink noise".to_string(),
+            StimulusKind::NarrowbandNoise { .. } => "1/3-octave bands of noise".to_string(),
+        }
+    }
+}
+
+fn default_warble_rate() -> f32 {
+    5.0
+}
+
+fn default_warble_depth() -> f32 {
+    0.05
+}
+
+/// A test tone: what is played, independent of how loud.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stimulus {
+    pub kind: StimulusKind,
+    pub freq: f32,
+    /// Output channel the tone is played on.
+    pub channel: u16,
+    /// Number of output channels of the device.
+    pub channels: u16,
+    /// Steady tone instead of on/off bursts.
+    pub continuous: bool,
+    /// On/off times and fades of the bursts.
+    pub timing: Timing,
+    /// Contralateral masking noise played along with the tone.
+    pub masker: Option<Masker>,
+}
+
+impl Stimulus {
+    pub fn new(
+        kind: StimulusKind,
+        freq: f32,
+        channel: u16,
+        channels: u16,
+        timing: Timing,
+    ) -> Stimulus {
+        Stimulus {
+            kind,
+            freq,
+            channel,
+            channels,
+            continuous: false,
+            timing,
+            masker: None,
+        }
+    }
+
+    /// A steady pure tone, e.g. for calibration with a sound level meter.
+    pub fn continuous(freq: f32, channel: u16, channels: u16) -> Stimulus {
+        Stimulus {
+            kind: StimulusKind::Sine,
+            freq,
+            channel,
+            channels,
+            continuous: true,
+            timing: Timing::default(),
+            masker: None,
+        }
+    }
+
+    /// The same stimulus with `masker` added.
+    pub fn masked(self, masker: Masker) -> Stimulus {
+        Stimulus {
+            masker: Some(masker),
+            ..self
+        }
+    }
+
+    /// Builds the source to play at `level`, mixed with the masker if any.
+    /// The returned handle keeps the masker level when the tone level changes.
+    pub fn build(&self, level: f64) -> (Box<dyn Source<Item = f32> + Send>, Option<MaskerGain>) {
+        match self.masker {
+            Some(masker) => {
+                let gain = MaskerGain::new(masker, level);
+                let source = MaskedTone::new(self.source(), self.freq, gain.clone());
+                (Box::new(source), Some(gain))
+            }
+            None => (self.source(), None),
+        }
+    }
+
+    /// Builds the sample source for the tone alone at full scale.
+    pub fn source(&self) -> Box<dyn Source<Item = f32> + Send> {
+        match self.kind {
+            StimulusKind::Sine if self.continuous => {
+                Box::new(SineWave::continuous(self.freq, self.channel, self.channels))
+            }
+            StimulusKind::Sine => Box::new(SineWave::new(
+                self.freq,
+                self.timing,
+                self.channel,
+                self.channels,
+            )),
+            StimulusKind::Warble { rate, depth } => Box::new(WarbleTone::new(
+                self.freq,
+                rate,
+                depth,
+                self.timing,
+                self.channel,
+                self.channels,
+            )),
+            StimulusKind::WhiteNoise { seed } => Box::new(Noise::new(
+                NoiseBand::White,
+                seed,
+                self.timing,
+                self.channel,
+                self.channels,
+            )),
+            StimulusKind::PinkNoise { seed } => Box::new(Noise::new(
+                NoiseBand::Pink,
+                seed,
+                self.timing,
+                self.channel,
+                self.channels,
+            )),
+            StimulusKind::NarrowbandNoise { seed } => Box::new(Noise::new(
+                NoiseBand::ThirdOctave(self.freq),
+                seed,
+                self.timing,
+                self.channel,
+                self.channels,
+            )),
+        }
+    }
+}
@@ -0,0 +1,605 @@
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+use std::fs;
+use std::path::Path;
+
+use crate::calibration::{Calibration, Scale};
+use crate::csv;
+use crate::model::{EarResult, Session, ThresholdPoint, Trial};
+use crate::subject::Subject;
+
+/// File name of the database in the output directory.
+pub const FILE_NAME: &str = "history.sqlite";
+
+/// Schema migrations, applied in order. `PRAGMA user_version` holds the number
+/// of migrations already applied; append new ones, never edit old ones.
+const MIGRATIONS: [&str; 7] = [
+    "
+CREATE TABLE subjects (
+    id INTEGER PRIMARY KEY,
+    code TEXT NOT NULL UNIQUE
+);
+CREATE TABLE calibrations (
+    id INTEGER PRIMARY KEY,
+    headphone TEXT NOT NULL,
+    -- the whole `Calibration` as YAML
+    yaml TEXT NOT NULL UNIQUE
+);
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY,
+    date TEXT NOT NULL UNIQUE,
+    subject_id INTEGER REFERENCES subjects(id),
+    calibration_id INTEGER REFERENCES calibrations(id),
+    memo TEXT NOT NULL,
+    device TEXT,
+    calibration_profile TEXT,
+    procedure TEXT,
+    stimulus TEXT,
+    scale TEXT NOT NULL,
+    started TEXT,
+    finished TEXT
+);
+CREATE TABLE thresholds (
+    id INTEGER PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    transducer TEXT NOT NULL,
+    ear TEXT NOT NULL,
+    freq REAL NOT NULL,
+    level REAL NOT NULL,
+    masker_level REAL,
+    presentations INTEGER NOT NULL,
+    heard INTEGER NOT NULL,
+    UNIQUE (session_id, transducer, ear, freq)
+);
+",
+    "
+CREATE TABLE trials (
+    id INTEGER PRIMARY KEY,
+    threshold_id INTEGER NOT NULL REFERENCES thresholds(id) ON DELETE CASCADE,
+    onset REAL NOT NULL,
+    level REAL NOT NULL,
+    masker_level REAL,
+    response TEXT NOT NULL,
+    reaction_time REAL NOT NULL
+);
+",
+    "
+ALTER TABLE subjects ADD COLUMN name TEXT NOT NULL DEFAULT '';
+ALTER TABLE subjects ADD COLUMN birth_year INTEGER;
+ALTER TABLE subjects ADD COLUMN notes TEXT NOT NULL DEFAULT '';
+",
+    "
+ALTER TABLE thresholds ADD COLUMN no_response INTEGER NOT NULL DEFAULT 0;
+",
+    "
+ALTER TABLE thresholds ADD COLUMN no_plateau INTEGER NOT NULL DEFAULT 0;
+",
+    "
+CREATE TABLE new_sessions (
+    id INTEGER PRIMARY KEY,
+    date TEXT NOT NULL,
+    subject_id INTEGER REFERENCES subjects(id),
+    calibration_id INTEGER REFERENCES calibrations(id),
+    memo TEXT NOT NULL,
+    device TEXT,
+    calibration_profile TEXT,
+    procedure TEXT,
+    stimulus TEXT,
+    scale TEXT NOT NULL,
+    started TEXT,
+    finished TEXT,
+    UNIQUE (subject_id, date)
+);
+INSERT INTO new_sessions (id, date, subject_id, calibration_id, memo, device,
+    calibration_profile, procedure, stimulus, scale, started, finished)
+SELECT id, date, subject_id, calibration_id, memo, device,
+    calibration_profile, procedure, stimulus, scale, started, finished
+FROM sessions;
+DROP TABLE sessions;
+ALTER TABLE new_sessions RENAME TO sessions;
+",
+    "
+ALTER TABLE sessions ADD COLUMN calibration_fingerprint TEXT;
+",
+];
+
+/// Embedded SQLite history of every session: subjects, sessions,
+/// calibrations and thresholds.
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens or creates the database at `path` and brings its schema up to date.
+    pub fn open(path: &Path) -> rusqlite::Result<Store> {
+        if let Some(dir) = path.parent() {
+            if fs::metadata(dir).is_err() {
+                fs::create_dir_all(dir).unwrap();
+            }
+        }
+        Store::migrate(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Store> {
+        Store::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(mut conn: Connection) -> rusqlite::Result<Store> {
+        // off while tables are rebuilt, or dropping one would cascade
+        conn.pragma_update(None, "foreign_keys", false)?;
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(Store { conn })
+    }
+
+    pub fn schema_version(&self) -> rusqlite::Result<usize> {
+        self.conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+    }
+
+    /// Saves `session`, replacing any stored session of the same subject and
+    /// date.
+    pub fn insert_session(&mut self, session: &Session) -> rusqlite::Result<i64> {
+        let tx = self.conn.transaction()?;
+        let subject_id = match &session.subject {
+            Some(code) => Some(subject_id(&tx, code)?),
+            None => None,
+        };
+        tx.execute(
+            "DELETE FROM sessions WHERE subject_id IS ?1 AND date = ?2",
+            params![subject_id, session.date],
+        )?;
+
+        let calibration_id = match &session.calibration {
+            Some(calibration) => Some(calibration_id(&tx, calibration)?),
+            None => None,
+        };
+        tx.execute(
+            "INSERT INTO sessions (date, subject_id, calibration_id, memo, device,
+                calibration_profile, procedure, stimulus, scale, started, finished,
+                calibration_fingerprint)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                session.date,
+                subject_id,
+                calibration_id,
+                session.memo,
+                session.device,
+                session.calibration_profile,
+                session.procedure.map(|procedure| to_yaml(&procedure)),
+                session.stimulus.map(|stimulus| to_yaml(&stimulus)),
+                to_yaml(&session.scale),
+                session.started,
+                session.finished,
+                session.calibration_fingerprint,
+            ],
+        )?;
+        let session_id = tx.last_insert_rowid();
+
+        for ear in &session.ears {
+            for point in &ear.points {
+                tx.execute(
+                    "INSERT INTO thresholds (session_id, transducer, ear, freq, level,
+                        masker_level, presentations, heard, no_response, no_plateau)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        session_id,
+                        ear.transducer,
+                        ear.ear,
+                        point.freq,
+                        point.level,
+                        point.masker_level,
+                        point.presentations,
+                        point.heard,
+                        point.no_response,
+                        point.no_plateau,
+                    ],
+                )?;
+                let threshold_id = tx.last_insert_rowid();
+                for trial in &point.trials {
+                    tx.execute(
+                        "INSERT INTO trials (threshold_id, onset, level, masker_level,
+                            response, reaction_time)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![
+                            threshold_id,
+                            trial.onset,
+                            trial.level,
+                            trial.masker_level,
+                            to_yaml(&trial.response),
+                            trial.reaction_time,
+                        ],
+                    )?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(session_id)
+    }
+
+    /// Whether a session of `subject` (`None` for none) at `date` is stored.
+    pub fn contains(&self, subject: Option<&str>, date: &str) -> rusqlite::Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT 1 FROM sessions s
+                 LEFT JOIN subjects ON subjects.id = s.subject_id
+                 WHERE subjects.code IS ?1 AND s.date = ?2",
+                params![subject, date],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|row| row.is_some())
+    }
+
+    /// Registered subject with this ID.
+    pub fn subject(&self, id: &str) -> rusqlite::Result<Option<Subject>> {
+        self.conn
+            .query_row(
+                "SELECT code, name, birth_year, notes FROM subjects WHERE code = ?1",
+                [id],
+                subject_from_row,
+            )
+            .optional()
+    }
+
+    /// Every registered subject, by ID.
+    pub fn subjects(&self) -> rusqlite::Result<Vec<Subject>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT code, name, birth_year, notes FROM subjects ORDER BY code")?;
+        let rows = statement.query_map([], subject_from_row)?;
+        rows.collect()
+    }
+
+    /// Registers `subject`, or updates it when the ID is already known.
+    pub fn save_subject(&mut self, subject: &Subject) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO subjects (code, name, birth_year, notes) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (code) DO UPDATE
+             SET name = excluded.name, birth_year = excluded.birth_year, notes = excluded.notes",
+            params![subject.id, subject.name, subject.birth_year, subject.notes],
+        )?;
+        Ok(())
+    }
+
+    /// Stored sessions of one subject, or of everyone with `None`, oldest first.
+    pub fn sessions(&self, subject: Option<&str>) -> rusqlite::Result<Vec<Session>> {
+        let mut statement = self.conn.prepare(
+            "SELECT s.id, s.date, subjects.code, s.memo, s.device, s.calibration_profile,
+                calibrations.yaml, s.procedure, s.stimulus, s.scale, s.started, s.finished,
+                s.calibration_fingerprint
+             FROM sessions s
+             LEFT JOIN subjects ON subjects.id = s.subject_id
+             LEFT JOIN calibrations ON calibrations.id = s.calibration_id
+             WHERE ?1 IS NULL OR subjects.code = ?1
+             ORDER BY s.date, subjects.code",
+        )?;
+        let rows = statement.query_map([subject], |row| {
+            let calibration: Option<String> = row.get(6)?;
+            let procedure: Option<String> = row.get(7)?;
+            let stimulus: Option<String> = row.get(8)?;
+            let scale: String = row.get(9)?;
+            let session = Session {
+                date: row.get(1)?,
+                subject: row.get(2)?,
+                memo: row.get(3)?,
+                device: row.get(4)?,
+                calibration_profile: row.get(5)?,
+                calibration: calibration.map(|yaml| from_yaml(&yaml)),
+                calibration_fingerprint: row.get(12)?,
+                procedure: procedure.map(|yaml| from_yaml(&yaml)),
+                stimulus: stimulus.map(|yaml| from_yaml(&yaml)),
+                scale: from_yaml::<Scale>(&scale),
+                started: row.get(10)?,
+                finished: row.get(11)?,
+                ears: Vec::new(),
+            };
+            Ok((row.get::<_, i64>(0)?, session))
+        })?;
+
+        let mut sessions = Vec::new();
+        for row in rows {
+            let (id, mut session) = row?;
+            session.ears = self.ears(id)?;
+            sessions.push(session);
+        }
+        Ok(sessions)
+    }
+
+    /// Thresholds of one session, grouped by transducer and ear in test order.
+    fn ears(&self, session_id: i64) -> rusqlite::Result<Vec<EarResult>> {
+        let mut statement = self.conn.prepare(
+            "SELECT transducer, ear, freq, level, masker_level, presentations, heard, id,
+                no_response, no_plateau
+             FROM thresholds WHERE session_id = ?1 ORDER BY id",
+        )?;
+        let mut rows = statement.query([session_id])?;
+        let mut ears: Vec<EarResult> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let transducer: String = row.get(0)?;
+            let ear: String = row.get(1)?;
+            let point = ThresholdPoint {
+                masker_level: row.get(4)?,
+                presentations: row.get(5)?,
+                heard: row.get(6)?,
+                trials: self.trials(row.get(7)?)?,
+                no_response: row.get(8)?,
+                no_plateau: row.get(9)?,
+                ..ThresholdPoint::new(row.get(2)?, row.get(3)?)
+            };
+            match ears
+                .iter_mut()
+                .find(|result| result.transducer == transducer && result.ear == ear)
+            {
+                Some(result) => result.points.push(point),
+                None => {
+                    let mut result = EarResult::new(&transducer, &ear);
+                    result.points.push(point);
+                    ears.push(result);
+                }
+            }
+        }
+        Ok(ears)
+    }
+
+    fn trials(&self, threshold_id: i64) -> rusqlite::Result<Vec<Trial>> {
+        let mut statement = self.conn.prepare(
+            "SELECT onset, level, masker_level, response, reaction_time
+             FROM trials WHERE threshold_id = ?1 ORDER BY id",
+        )?;
+        let rows = statement.query_map([threshold_id], |row| {
+            let response: String = row.get(3)?;
+            Ok(Trial {
+                onset: row.get(0)?,
+                level: row.get(1)?,
+                masker_level: row.get(2)?,
+                response: from_yaml(&response),
+                reaction_time: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Imports every session of the `result_*.csv` files in `dir_path` that is
+    /// not stored yet, as sessions of `subject` unless the file names one.
+    /// Returns the number of sessions imported; nothing is imported when a
+    /// file cannot be read.
+    pub fn import_csv_dir(
+        &mut self,
+        dir_path: &str,
+        subject: Option<&str>,
+    ) -> Result<usize, String> {
+        let mut imported = 0;
+        for mut session in csv::load_all(dir_path)? {
+            if session.subject.is_none() {
+                session.subject = subject.map(str::to_string);
+            }
+            let stored = self
+                .contains(session.subject.as_deref(), &session.date)
+                .map_err(|err| err.to_string())?;
+            if !stored {
+                self.insert_session(&session)
+                    .map_err(|err| err.to_string())?;
+                imported += 1;
+            }
+        }
+        Ok(imported)
+    }
+}
+
+fn subject_from_row(row: &Row) -> rusqlite::Result<Subject> {
+    Ok(Subject {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        birth_year: row.get(2)?,
+        notes: row.get(3)?,
+    })
+}
+
+fn subject_id(tx: &Transaction, code: &str) -> rusqlite::Result<i64> {
+    tx.execute("INSERT OR IGNORE INTO subjects (code) VALUES (?1)", [code])?;
+    tx.query_row("SELECT id FROM subjects WHERE code = ?1", [code], |row| {
+        row.get(0)
+    })
+}
+
+fn calibration_id(tx: &Transaction, calibration: &Calibration) -> rusqlite::Result<i64> {
+    let yaml = to_yaml(calibration);
+    tx.execute(
+        "INSERT OR IGNORE INTO calibrations (headphone, yaml) VALUES (?1, ?2)",
+        params![calibration.headphone, yaml],
+    )?;
+    tx.query_row(
+        "SELECT id FROM calibrations WHERE yaml = ?1",
+        [&yaml],
+        |row| row.get(0),
+    )
+}
+
+fn to_yaml<T: serde::Serialize>(value: &T) -> String {
+    serde_yaml::to_string(value).unwrap().trim_end().to_string()
+}
+
+fn from_yaml<T: serde::de::DeserializeOwned>(yaml: &str) -> T {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procedure::{Procedure, Response};
+
+    fn session(date: &str, subject: Option<&str>) -> Session {
+        let mut session = Session::new(date);
+        session.subject = subject.map(str::to_string);
+        session.procedure = Some(Procedure::HughsonWestlake);
+        for ear in ["L", "R"] {
+            let mut result = EarResult::new("headphone", ear);
+            for (freq, level) in [(62.5, -40.0), (1000.0, -65.0), (125.0, -50.0)] {
+                result.points.push(ThresholdPoint {
+                    presentations: 2,
+                    heard: 1,
+                    trials: vec![
+                        Trial {
+                            onset: 1.5,
+                            level: level - 10.0,
+                            masker_level: None,
+                            response: Response::NotHeard,
+                            reaction_time: 1.6,
+                        },
+                        Trial {
+                            onset: 3.9,
+                            level,
+                            masker_level: Some(-30.0),
+                            response: Response::Heard,
+                            reaction_time: 0.35,
+                        },
+                    ],
+                    ..ThresholdPoint::new(freq, level)
+                });
+            }
+            session.ears.push(result);
+        }
+        session
+    }
+
+    #[test]
+    fn test_migrations_are_idempotent() {
+        let dir = std::env::temp_dir().join("hearing-test-store-migrations");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("history.sqlite");
+        let store = Store::open(&path).unwrap();
+        assert_eq!(store.schema_version().unwrap(), MIGRATIONS.len());
+        drop(store);
+        let store = Store::open(&path).unwrap();
+        assert_eq!(store.schema_version().unwrap(), MIGRATIONS.len());
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut store = Store::open_in_memory().unwrap();
+        let first = session("20230101_100000", Some("S01"));
+        let mut second = session("20230102_100000", Some("S01"));
+        second.ears[1].points[0].masker_level = Some(-35.0);
+        second.ears[1].points[0].no_plateau = true;
+        second.ears[1].points[1].no_response = true;
+        second.calibration_fingerprint = Some("5f0d6b2e1c4a9f83".to_string());
+        store.insert_session(&second).unwrap();
+        store.insert_session(&first).unwrap();
+        // saving again replaces the stored session
+        store.insert_session(&first).unwrap();
+        // but not another subject's session of the same second
+        let other = session("20230101_100000", Some("S02"));
+        store.insert_session(&other).unwrap();
+        assert!(store.contains(Some("S02"), "20230101_100000").unwrap());
+        assert!(!store.contains(None, "20230101_100000").unwrap());
+
+        assert_eq!(store.sessions(None).unwrap(), vec![first, other, second]);
+        let subjects: i64 = store
+            .conn
+            .query_row("SELECT COUNT(*) FROM subjects", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(subjects, 2);
+    }
+
+    #[test]
+    fn test_session_key_migration_keeps_thresholds() {
+        let conn = Connection::open_in_memory().unwrap();
+        for (i, migration) in MIGRATIONS[..5].iter().enumerate() {
+            conn.execute_batch(migration).unwrap();
+            conn.pragma_update(None, "user_version", i + 1).unwrap();
+        }
+        conn.execute_batch(
+            "INSERT INTO sessions (id, date, memo, scale) VALUES (1, '20230101_100000', '', 'dbfs');
+             INSERT INTO thresholds (session_id, transducer, ear, freq, level, presentations, heard)
+             VALUES (1, 'headphone', 'L', 1000, -50, 0, 0);",
+        )
+        .unwrap();
+        let store = Store::migrate(conn).unwrap();
+        let sessions = store.sessions(None).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].ears[0].points.len(), 1);
+    }
+
+    #[test]
+    fn test_subject_registry() {
+        let mut store = Store::open_in_memory().unwrap();
+        let mut subject = Subject::new("S02").unwrap();
+        subject.name = "Blue Heron".to_string();
+        subject.birth_year = Some(1984);
+        store.save_subject(&subject).unwrap();
+        subject.notes = "tinnitus, left".to_string();
+        store.save_subject(&subject).unwrap();
+        assert_eq!(store.subject("S02").unwrap(), Some(subject.clone()));
+        assert_eq!(store.subject("S03").unwrap(), None);
+
+        // sessions register unknown subjects and keep known ones as they are
+        store
+            .insert_session(&session("20230101_100000", Some("S01")))
+            .unwrap();
+        store
+            .insert_session(&session("20230102_100000", Some("S02")))
+            .unwrap();
+        store
+            .insert_session(&session("20230103_100000", None))
+            .unwrap();
+        let ids: Vec<String> = store
+            .subjects()
+            .unwrap()
+            .into_iter()
+            .map(|subject| subject.id)
+            .collect();
+        assert_eq!(ids, ["S01", "S02"]);
+        assert_eq!(store.subject("S02").unwrap(), Some(subject));
+
+        let history = store.sessions(Some("S02")).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].date, "20230102_100000");
+        assert_eq!(store.sessions(None).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_import_csv_dir() {
+        let dir = std::env::temp_dir().join("hearing-test-store-import");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("result_01.csv"),
+            "date,LR,125,250\n20221128_205550,L,-30,-40\n20221128_205550,R,-31,-41\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("result_02.csv"),
+            "date,LR,62.5,125\n20221129_090000,headphone:L,12.5,10\n20221129_090000,headphone:R,15,20\n",
+        )
+        .unwrap();
+
+        let mut store = Store::open_in_memory().unwrap();
+        assert_eq!(
+            store.import_csv_dir(dir.to_str().unwrap(), None).unwrap(),
+            2
+        );
+        assert_eq!(
+            store.import_csv_dir(dir.to_str().unwrap(), None).unwrap(),
+            0
+        );
+
+        let sessions = store.sessions(None).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].ears[0].key(), "L");
+        assert_eq!(sessions[0].scale, Scale::Dbfs);
+        assert_eq!(sessions[1].ears[1].key(), "headphone:R");
+        assert_eq!(sessions[1].ears[1].points[0].freq, 62.5);
+        assert_eq!(sessions[1].scale, Scale::Dbfs);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A person in the subject registry of the history database.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Subject {
+    /// Short code such as "S01", also the name of the subject's result
+    /// directory. Letters, digits, '-' and '_' only.
+    pub id: String,
+    /// Name or pseudonym.
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub birth_year: Option<u32>,
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl Subject {
+    pub fn new(id: &str) -> Result<Subject, String> {
+        if !is_valid_id(id) {
+            return Err(format!(
+                "subject ID {:?} may only contain letters, digits, '-' and '_'",
+                id
+            ));
+        }
+        Ok(Subject {
+            id: id.to_string(),
+            name: String::new(),
+            birth_year: None,
+            notes: String::new(),
+        })
+    }
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Directory of the CSV results, plots and exports of `subject`: a
+/// subdirectory of `base_dir` named after the subject, or `base_dir` itself
+/// for sessions without one.
+pub fn result_dir(base_dir: &str, subject: Option<&str>) -> String {
+    match subject {
+        Some(id) => Path::new(base_dir).join(id).to_str().unwrap().to_string(),
+        None => base_dir.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject_ids() {
+        assert!(Subject::new("S01").is_ok());
+        assert!(Subject::new("pilot_2-b").is_ok());
+        // IDs become directory names
+        assert!(Subject::new("").is_err());
+        assert!(Subject::new("../S01").is_err());
+        assert!(Subject::new("S 01").is_err());
+
+        assert_eq!(
+            Path::new(&result_dir("./result", Some("S01"))),
+            Path::new("./result/S01")
+        );
+        assert_eq!(result_dir("./result", None), "./result");
+    }
+}
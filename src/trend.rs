@@ -0,0 +1,178 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::model::Session;
+
+/// How the sessions of one subject are compared, `plot.history` in
+/// `config.yaml`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct HistoryConfig {
+    /// Number of most recent audiograms drawn over each other.
+    #[serde(default = "default_overlay")]
+    pub overlay: usize,
+    /// Change from the baseline in dB that counts as a significant shift.
+    #[serde(default = "default_shift")]
+    pub shift: f32,
+}
+
+fn default_overlay() -> usize {
+    5
+}
+
+fn default_shift() -> f32 {
+    10.0
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            overlay: default_overlay(),
+            shift: default_shift(),
+        }
+    }
+}
+
+/// A threshold that moved at least the criterion away from its baseline.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Shift {
+    /// Transducer and ear, as `EarResult::key`.
+    pub key: String,
+    pub freq: f32,
+    /// Date of the session the threshold was first measured in.
+    pub baseline: String,
+    pub date: String,
+    /// New minus baseline level. Positive is worse hearing, in dB HL as well
+    /// as in dBFS.
+    pub change: f32,
+}
+
+/// The sessions that can be compared with the latest one, oldest first:
+/// dBFS and dB HL results are not on the same scale.
+pub fn comparable(mut sessions: Vec<Session>) -> Vec<Session> {
+    sessions.sort_by(|a, b| a.date.cmp(&b.date));
+    let Some(scale) = sessions.last().map(|session| session.scale) else {
+        return sessions;
+    };
+    sessions.retain(|session| session.scale == scale);
+    sessions
+}
+
+/// Every threshold of `sessions` (oldest first) that differs by `criterion`
+/// dB or more from the first session to test the same transducer, ear and
+/// frequency. No-response levels are not thresholds and are skipped.
+pub fn significant_shifts(sessions: &[Session], criterion: f32) -> Vec<Shift> {
+    let mut baselines: Vec<(String, f32, &str, f32)> = Vec::new();
+    let mut shifts = Vec::new();
+    for session in sessions {
+        for ear in &session.ears {
+            let key = ear.key();
+            for point in ear.points.iter().filter(|point| !point.no_response) {
+                let baseline = baselines
+                    .iter()
+                    .find(|(k, freq, _, _)| *k == key && *freq == point.freq);
+                match baseline {
+                    Some((_, _, date, level)) => {
+                        let change = point.level - level;
+                        if change.abs() >= criterion {
+                            shifts.push(Shift {
+                                key: key.clone(),
+                                freq: point.freq,
+                                baseline: date.to_string(),
+                                date: session.date.clone(),
+                                change,
+                            });
+                        }
+                    }
+                    None => baselines.push((key.clone(), point.freq, &session.date, point.level)),
+                }
+            }
+        }
+    }
+    shifts
+}
+
+/// Days since the epoch of a session date (`%Y%m%d_%H%M%S`), for time axes.
+pub fn days(date: &str) -> f64 {
+    let time = NaiveDateTime::parse_from_str(date, "%Y%m%d_%H%M%S").unwrap();
+    time.and_utc().timestamp() as f64 / 86400.0
+}
+
+/// The calendar date `days` after the epoch.
+pub fn day_label(days: f64) -> String {
+    match chrono::DateTime::from_timestamp((days * 86400.0) as i64, 0) {
+        Some(time) => time.format("%Y-%m-%d").to_string(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::Scale;
+    use crate::model::{EarResult, ThresholdPoint};
+
+    fn session(date: &str, scale: Scale, levels: &[(f32, f32)]) -> Session {
+        let mut session = Session::new(date);
+        session.scale = scale;
+        let mut ear = EarResult::new("headphone", "R");
+        ear.points = levels
+            .iter()
+            .map(|(freq, level)| ThresholdPoint::new(*freq, *level))
+            .collect();
+        session.ears = vec![ear];
+        session
+    }
+
+    #[test]
+    fn test_significant_shifts() {
+        let sessions = comparable(vec![
+            session("20240301_090000", Scale::HearingLevel, &[(4000.0, 20.0)]),
+            session(
+                "20240101_090000",
+                Scale::HearingLevel,
+                &[(1000.0, 5.0), (4000.0, 10.0)],
+            ),
+            session("20240201_090000", Scale::Dbfs, &[(1000.0, -60.0)]),
+            session(
+                "20240401_090000",
+                Scale::HearingLevel,
+                &[(1000.0, -5.0), (4000.0, 25.0)],
+            ),
+        ]);
+        let dates: Vec<&str> = sessions.iter().map(|s| s.date.as_str()).collect();
+        assert_eq!(
+            dates,
+            ["20240101_090000", "20240301_090000", "20240401_090000"]
+        );
+
+        let shifts = significant_shifts(&sessions, 10.0);
+        assert_eq!(
+            shifts,
+            [
+                Shift {
+                    key: "headphone:R".to_string(),
+                    freq: 4000.0,
+                    baseline: "20240101_090000".to_string(),
+                    date: "20240301_090000".to_string(),
+                    change: 10.0,
+                },
+                Shift {
+                    key: "headphone:R".to_string(),
+                    freq: 1000.0,
+                    baseline: "20240101_090000".to_string(),
+                    date: "20240401_090000".to_string(),
+                    change: -10.0,
+                },
+                Shift {
+                    key: "headphone:R".to_string(),
+                    freq: 4000.0,
+                    baseline: "20240101_090000".to_string(),
+                    date: "20240401_090000".to_string(),
+                    change: 15.0,
+                },
+            ]
+        );
+
+        assert_eq!(day_label(days("20240401_090000")), "2024-04-01");
+    }
+}
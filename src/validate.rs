@@ -0,0 +1,730 @@
+use std::fmt;
+
+use crate::calibration::Calibration;
+use crate::channel_map::EARS;
+use crate::listener::ListenerConfig;
+use crate::output::SAMPLE_RATE;
+use crate::stimulus::StimulusKind;
+use crate::Point;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem with the config, at the 1-based line and column it was found
+/// at when that is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((line, column)) = self.location {
+            write!(f, "{}:{}: ", line, column)?;
+        }
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+/// Parses the YAML text of a config. Typos in keys are errors, so they are
+/// not silently replaced by defaults.
+pub fn parse(source: &str) -> Result<Point, Diagnostic> {
+    serde_yaml::from_str(source).map_err(|err| {
+        let location = err
+            .location()
+            .map(|location| (location.line(), location.column()));
+        // the location is reported separately
+        let message = err.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) if location.is_some() => message[..index].to_string(),
+            _ => message,
+        };
+        Diagnostic {
+            severity: Severity::Error,
+            location,
+            message,
+        }
+    })
+}
+
+/// Range checks that serde cannot do. `source` is the YAML text `config` was
+/// parsed from, used to point at the offending values.
+pub fn check(config: &Point, source: Option<&str>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |severity, key: &str, nth: usize, value: &dyn Fn(&str) -> bool, message| {
+        let location = source.and_then(|source| locate(source, key, value).get(nth).copied());
+        diagnostics.push(Diagnostic {
+            severity,
+            location,
+            message,
+        });
+    };
+    let nyquist = SAMPLE_RATE as f32 / 2.0;
+
+    if config.test_freq.is_empty() {
+        report(
+            Severity::Error,
+            "test_freq",
+            0,
+            &|_| false,
+            "test_freq: no frequencies to test".to_string(),
+        );
+    }
+    for (i, freq) in config.test_freq.iter().enumerate() {
+        let is_freq = |token: &str| token.parse::<f32>() == Ok(*freq);
+        let nth = config.test_freq[..i].iter().filter(|f| *f == freq).count();
+        if nth == 1 {
+            report(
+                Severity::Error,
+                "test_freq",
+                nth,
+                &is_freq,
+                format!("test_freq: {} Hz is listed more than once", freq),
+            );
+        }
+        if freq.is_nan() || *freq <= 0.0 {
+            report(
+                Severity::Error,
+                "test_freq",
+                nth,
+                &is_freq,
+                format!("test_freq: {} Hz is not a positive frequency", freq),
+            );
+            continue;
+        }
+        let highest = highest_freq(config.stimulus, *freq);
+        if highest >= nyquist {
+            report(
+                Severity::Error,
+                "test_freq",
+                nth,
+                &is_freq,
+                format!(
+                    "test_freq: {} Hz {}is above the {} Hz limit of a {} Hz output",
+                    freq,
+                    stimulus_reach(config.stimulus, highest),
+                    nyquist,
+                    SAMPLE_RATE
+                ),
+            );
+            continue;
+        }
+        if nth > 0 {
+            continue;
+        }
+        if let Some(calibration) = &config.calibration {
+            let (low, high) = calibrated_range(calibration);
+            if *freq < low || *freq > high {
+                report(
+                    Severity::Warning,
+                    "test_freq",
+                    nth,
+                    &is_freq,
+                    format!(
+                        "test_freq: {} Hz is outside the calibrated range {}-{} Hz of {}; \
+                         the session will stay in dBFS",
+                        freq, low, high, calibration.headphone
+                    ),
+                );
+            }
+        }
+    }
+
+    if config.ears.is_empty() {
+        report(
+            Severity::Error,
+            "ears",
+            0,
+            &|_| false,
+            "ears: no ears to test".to_string(),
+        );
+    }
+    for (i, ear) in config.ears.iter().enumerate() {
+        let is_ear = |token: &str| token.trim_matches(|c| c == '"' || c == '\'') == ear;
+        let nth = config.ears[..i].iter().filter(|e| *e == ear).count();
+        if !EARS.contains(&ear.as_str()) {
+            report(
+                Severity::Error,
+                "ears",
+                nth,
+                &is_ear,
+                format!("ears: unknown ear {:?}, expected L or R", ear),
+            );
+        } else if nth == 1 {
+            report(
+                Severity::Error,
+                "ears",
+                nth,
+                &is_ear,
+                format!("ears: {} is listed more than once", ear),
+            );
+        } else if let Some(calibration) = &config.calibration {
+            if calibration.offsets.table(ear).is_none() {
+                report(
+                    Severity::Error,
+                    "ears",
+                    nth,
+                    &is_ear,
+                    format!(
+                        "ears: the calibration of {} has no offsets for {}",
+                        calibration.headphone, ear
+                    ),
+                );
+            }
+        }
+    }
+
+    let timing = config.timing;
+    for (key, value) in [
+        ("off", timing.off),
+        ("fade", timing.fade),
+        ("pause", timing.pause),
+    ] {
+        if value.is_nan() || value < 0.0 {
+            report(
+                Severity::Error,
+                "timing",
+                0,
+                &|token| token == key,
+                format!("timing: {} must not be negative", key),
+            );
+        }
+    }
+    if timing.on.is_nan() || timing.on <= 2.0 * timing.fade {
+        report(
+            Severity::Error,
+            "timing",
+            0,
+            &|token| token == "on",
+            format!(
+                "timing: on ({} s) must be longer than the fade-in and fade-out ({} s each)",
+                timing.on, timing.fade
+            ),
+        );
+    }
+
+    if let StimulusKind::Warble { rate, depth } = config.stimulus {
+        if rate.is_nan() || rate <= 0.0 {
+            report(
+                Severity::Error,
+                "stimulus",
+                0,
+                &|token| token == "rate",
+                "stimulus: warble rate must be positive".to_string(),
+            );
+        }
+        if !(depth > 0.0 && depth < 1.0) {
+            report(
+                Severity::Error,
+                "stimulus",
+                0,
+                &|token| token == "depth",
+                format!("stimulus: warble depth ({}) must be between 0 and 1", depth),
+            );
+        }
+    }
+
+    let levels = config.levels;
+    for (key, value) in [
+        ("step", levels.step),
+        ("step_down", levels.step_down),
+        ("step_up", levels.step_up),
+    ] {
+        if value.is_nan() || value <= 0.0 {
+            report(
+                Severity::Error,
+                "levels",
+                0,
+                &|token| token == key,
+                format!("levels: {} must be positive", key),
+            );
+        }
+    }
+    if !(levels.min <= levels.start && levels.start <= levels.max) {
+        report(
+            Severity::Error,
+            "levels",
+            0,
+            &|token| token == "start",
+            format!(
+                "levels: start ({} dBFS) is outside min..max ({}..{} dBFS)",
+                levels.start, levels.min, levels.max
+            ),
+        );
+    }
+    if levels.max > 0.0 {
+        report(
+            Severity::Warning,
+            "levels",
+            0,
+            &|token| token == "max",
+            format!("levels: max ({} dBFS) clips the tone", levels.max),
+        );
+    }
+
+    for (i, transducer) in config.channel_map.iter().enumerate() {
+        let is_name = |token: &str| token == transducer.transducer;
+        let nth = config.channel_map[..i]
+            .iter()
+            .filter(|t| t.transducer == transducer.transducer)
+            .count();
+        if nth == 1 {
+            report(
+                Severity::Error,
+                "channel_map",
+                nth,
+                &is_name,
+                format!(
+                    "channel_map: transducer {:?} is listed more than once",
+                    transducer.transducer
+                ),
+            );
+        }
+        if !(1..=2).contains(&transducer.channels) {
+            report(
+                Severity::Error,
+                "channel_map",
+                nth,
+                &is_name,
+                format!(
+                    "channel_map: {} has {} channels, expected 1 or 2",
+                    transducer.transducer, transducer.channels
+                ),
+            );
+        }
+        if let Some(calibration) = &config.calibration {
+            if transducer.transducer != calibration.transducer {
+                report(
+                    Severity::Error,
+                    "channel_map",
+                    nth,
+                    &is_name,
+                    format!(
+                        "channel_map: the calibration of {} is for {}, so {} results \
+                         cannot be converted to dB HL",
+                        calibration.headphone, calibration.transducer, transducer.transducer
+                    ),
+                );
+            }
+        }
+    }
+    // levels within `levels`, a plateau that can be reached and a masker for
+    // every transducer of the channel map
+    if let Some(masking) = &config.masking {
+        let mut error = |key: &str, message: String| {
+            report(
+                Severity::Error,
+                "masking",
+                0,
+                &|token| token == key,
+                format!("masking: {}", message),
+            )
+        };
+        let in_levels = |level: f64| levels.min <= level && level <= levels.max;
+        if !in_levels(masking.effective_level) {
+            error(
+                "effective_level",
+                format!(
+                    "effective_level ({} dBFS) is outside levels min..max ({}..{} dBFS)",
+                    masking.effective_level, levels.min, levels.max
+                ),
+            );
+        }
+        if let Some(criterion) = masking.criterion {
+            if criterion.is_nan() || criterion < 0.0 {
+                error("criterion", "criterion must not be negative".to_string());
+            }
+        }
+        if let Some(plateau) = &masking.plateau {
+            if plateau.step.is_nan() || plateau.step <= 0.0 {
+                error("step", "plateau step must be positive".to_string());
+            }
+            if plateau.width == 0 {
+                error("width", "plateau width must be at least 1".to_string());
+            }
+            if plateau.tolerance.is_nan() || plateau.tolerance < 0.0 {
+                error(
+                    "tolerance",
+                    "plateau tolerance must not be negative".to_string(),
+                );
+            }
+            if !in_levels(plateau.max_level) || plateau.max_level < masking.effective_level {
+                error(
+                    "max_level",
+                    format!(
+                        "plateau max_level ({} dBFS) must be within effective_level..levels max \
+                         ({}..{} dBFS)",
+                        plateau.max_level, masking.effective_level, levels.max
+                    ),
+                );
+            }
+        }
+        // one message per problem, not per transducer
+        let mut problems: Vec<String> = Vec::new();
+        for transducer in &config.channel_map {
+            if let Err(message) = masking.transducer(&config.channel_map, transducer) {
+                if !problems.contains(&message) {
+                    problems.push(message);
+                }
+            }
+        }
+        for message in problems {
+            error("transducer", message);
+        }
+    }
+
+    // every tested transducer and ear needs thresholds to answer from
+    if let ListenerConfig::Simulated { audiogram, .. } = &config.listener {
+        let mut checked = Vec::new();
+        for transducer in &config.channel_map {
+            for ear in config
+                .ears
+                .iter()
+                .filter(|ear| EARS.contains(&ear.as_str()))
+            {
+                let key = format!("{}:{}", transducer.transducer, ear);
+                let key = if audiogram.contains_key(&key) {
+                    key
+                } else {
+                    ear.clone()
+                };
+                if checked.contains(&key) {
+                    continue;
+                }
+                let Some(table) = audiogram.get(&key) else {
+                    report(
+                        Severity::Error,
+                        "listener",
+                        0,
+                        &|token| token == "audiogram",
+                        format!(
+                            "listener: the audiogram has no thresholds for {} or {}:{}",
+                            ear, transducer.transducer, ear
+                        ),
+                    );
+                    checked.push(key);
+                    continue;
+                };
+                let (low, high) = (table.keys().next(), table.keys().next_back());
+                let (low, high) = (*low.unwrap() as f32, *high.unwrap() as f32);
+                for freq in config.test_freq.iter().filter(|f| **f < low || **f > high) {
+                    report(
+                        Severity::Warning,
+                        "listener",
+                        0,
+                        &|token| token == key,
+                        format!(
+                            "listener: test_freq {} Hz is outside the {} audiogram ({}-{} Hz); \
+                             its threshold is held flat",
+                            freq, key, low, high
+                        ),
+                    );
+                }
+                checked.push(key);
+            }
+        }
+    }
+
+    let findings = config.findings;
+    for (key, value) in [
+        ("depth", findings.notch.depth),
+        ("recovery", findings.notch.recovery),
+        ("difference", findings.asymmetry.difference),
+    ] {
+        if value.is_nan() || value <= 0.0 {
+            report(
+                Severity::Error,
+                "findings",
+                0,
+                &|token| token == key,
+                format!("findings: {} must be positive", key),
+            );
+        }
+    }
+    if findings.asymmetry.adjacent == 0 {
+        report(
+            Severity::Error,
+            "findings",
+            0,
+            &|token| token == "adjacent",
+            "findings: adjacent must be at least 1".to_string(),
+        );
+    }
+
+    if let Some((width, height)) = config.plot.size {
+        if width == 0 || height == 0 {
+            report(
+                Severity::Error,
+                "plot",
+                0,
+                &|token| token == "size",
+                format!("plot: size {}x{} is empty", width, height),
+            );
+        }
+    }
+    diagnostics
+}
+
+/// Highest frequency `stimulus` produces for a test frequency `freq`.
+fn highest_freq(stimulus: StimulusKind, freq: f32) -> f32 {
+    match stimulus {
+        StimulusKind::Warble { depth, .. } => freq * (1.0 + depth),
+        // upper edge of the 1/3-octave band
+        StimulusKind::NarrowbandNoise { .. } => freq * 2f32.powf(1.0 / 6.0),
+        _ => freq,
+    }
+}
+
+/// How far `stimulus` reaches above its test frequency, for messages.
+fn stimulus_reach(stimulus: StimulusKind, highest: f32) -> String {
+    match stimulus {
+        StimulusKind::Warble { .. } => format!("(warble up to {} Hz) ", highest.round()),
+        StimulusKind::NarrowbandNoise { .. } => {
+            format!("(noise band up to {} Hz) ", highest.round())
+        }
+        _ => String::new(),
+    }
+}
+
+/// Frequencies covered by the RETSPL table and the offsets of every ear.
+fn calibrated_range(calibration: &Calibration) -> (f32, f32) {
+    let retspl = calibration.retspl.table();
+    let mut tables = vec![&retspl];
+    tables.extend(
+        calibration
+            .offsets
+            .tables()
+            .into_iter()
+            .map(|(_, table)| table),
+    );
+    let low = tables
+        .iter()
+        .filter_map(|table| table.keys().next())
+        .max()
+        .copied()
+        .unwrap_or(0);
+    let high = tables
+        .iter()
+        .filter_map(|table| table.keys().next_back())
+        .min()
+        .copied()
+        .unwrap_or(i32::MAX);
+    (low as f32, high as f32)
+}
+
+/// Positions of the values matching `value` under the top-level `key`, in
+/// order. The key itself when `value` never matches, e.g. for an empty list.
+fn locate(source: &str, key: &str, value: &dyn Fn(&str) -> bool) -> Vec<(usize, usize)> {
+    let lines: Vec<&str> = source.lines().collect();
+    let prefix = format!("{}:", key);
+    let Some(start) = lines.iter().position(|line| line.starts_with(&prefix)) else {
+        return Vec::new();
+    };
+    let mut positions = Vec::new();
+    for (number, line) in lines.iter().enumerate().skip(start) {
+        let is_top_level = !line.starts_with([' ', '\t', '#', '-']) && !line.is_empty();
+        if number > start && is_top_level {
+            break;
+        }
+        let content = line.split(" #").next().unwrap();
+        let offset = if number == start { prefix.len() } else { 0 };
+        let mut column = offset;
+        for token in content[offset..].split(|c: char| c.is_whitespace() || ",[]{}".contains(c)) {
+            if !token.is_empty() && value(token.trim_end_matches(':')) {
+                positions.push((number + 1, column + 1));
+            }
+            column += token.len() + 1;
+        }
+    }
+    if positions.is_empty() {
+        positions.push((start + 1, 1));
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+memo: validate
+test_freq: [62, 1000, 30000, 1000]
+ears:
+  - R
+  - X
+calibration:
+  headphone: DT 770
+  retspl: tdh39
+  offsets: {125: 90.0, 1000: 98.0, 8000: 88.0}
+";
+
+    #[test]
+    fn test_check() {
+        let config = parse(CONFIG).unwrap();
+        let messages: Vec<String> = check(&config, Some(CONFIG))
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "3:13: warning: test_freq: 62 Hz is outside the calibrated range 125-8000 Hz \
+                 of DT 770; the session will stay in dBFS",
+                "3:23: error: test_freq: 30000 Hz is above the 24000 Hz limit of a 48000 Hz \
+                 output",
+                "3:30: error: test_freq: 1000 Hz is listed more than once",
+                "6:5: error: ears: unknown ear \"X\", expected L or R",
+            ]
+        );
+
+        let source = "
+memo: timing
+test_freq: [1000]
+timing:
+  on: 0.04
+  fade: 0.025
+levels: {start: -24.0, step_up: 0, max: -30.0}
+";
+        let config = parse(source).unwrap();
+        let messages: Vec<String> = check(&config, Some(source))
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "5:3: error: timing: on (0.04 s) must be longer than the fade-in and fade-out \
+                 (0.025 s each)",
+                "7:24: error: levels: step_up must be positive",
+                "7:10: error: levels: start (-24 dBFS) is outside min..max (-120..-30 dBFS)",
+            ]
+        );
+
+        let source = "memo: ok\ntest_freq: [1000, 23000]\nstimulus: {kind: warble}\n";
+        let config = parse(source).unwrap();
+        let diagnostics = check(&config, Some(source));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].location, Some((2, 19)));
+        assert!(diagnostics[0].message.contains("(warble up to 24150 Hz)"));
+
+        let source = "
+memo: one ear
+test_freq: [1000]
+ears: [L, R]
+calibration: {headphone: DT 770, retspl: tdh39, offsets: {L: {125: 90.0, 8000: 88.0}}}
+";
+        let messages: Vec<String> = check(&parse(source).unwrap(), Some(source))
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            ["4:11: error: ears: the calibration of DT 770 has no offsets for R"]
+        );
+
+        let source = "
+memo: bone too
+test_freq: [1000]
+channel_map: [{transducer: headphone, channel: 0}, {transducer: bone, channel: 2, channels: 1}]
+calibration: {headphone: DT 770, retspl: tdh39, offsets: {125: 90.0, 8000: 88.0}}
+";
+        let messages: Vec<String> = check(&parse(source).unwrap(), Some(source))
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "4:65: error: channel_map: the calibration of DT 770 is for headphone, \
+              so bone results cannot be converted to dB HL"
+            ]
+        );
+
+        let source = "
+memo: mask
+test_freq: [1000]
+channel_map: [{transducer: bone, channel: 0, channels: 1}]
+masking:
+  effective_level: 10.0
+  plateau: {step: 0.0, max_level: -200.0}
+";
+        let messages: Vec<String> = check(&parse(source).unwrap(), Some(source))
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "6:3: error: masking: effective_level (10 dBFS) is outside levels min..max \
+              (-120..0 dBFS)",
+                "7:13: error: masking: plateau step must be positive",
+                "7:24: error: masking: plateau max_level (-200 dBFS) must be within \
+              effective_level..levels max (10..0 dBFS)",
+                "5:1: error: masking: bone has one output and the channel map has no \
+              two-channel transducer",
+            ]
+        );
+
+        let source = "
+memo: warble
+test_freq: [1000]
+stimulus: {kind: warble, rate: 0.0, depth: 1.5}
+";
+        let messages: Vec<String> = check(&parse(source).unwrap(), Some(source))
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "4:26: error: stimulus: warble rate must be positive",
+                "4:37: error: stimulus: warble depth (1.5) must be between 0 and 1",
+            ]
+        );
+
+        let source = "
+memo: listener
+test_freq: [125, 1000]
+ears: [L, R]
+channel_map: [{transducer: headphone, channel: 0}, {transducer: bone, channel: 2, channels: 1}]
+listener:
+  kind: simulated
+  audiogram:
+    L: {250: -60.0, 8000: -60.0}
+    bone:R: {1000: -40.0}
+";
+        let messages: Vec<String> = check(&parse(source).unwrap(), Some(source))
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "9:5: warning: listener: test_freq 125 Hz is outside the L audiogram \
+              (250-8000 Hz); its threshold is held flat",
+                "8:3: error: listener: the audiogram has no thresholds for R or headphone:R",
+                "10:5: warning: listener: test_freq 125 Hz is outside the bone:R audiogram \
+              (1000-1000 Hz); its threshold is held flat",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = parse("memo: typo\ntest-freq: [1000]\n").unwrap_err();
+        assert_eq!(error.location, Some((2, 1)));
+        assert!(error.message.starts_with("unknown field `test-freq`"));
+
+        let error = parse("memo: x\ntest_freq: [1000, loud]\n").unwrap_err();
+        assert_eq!(error.location, Some((2, 19)));
+    }
+}
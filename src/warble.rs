@@ -0,0 +1,115 @@
+use rodio::source::Source;
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use crate::gate::{Gate, Timing};
+
+/// A frequency-modulated ("warble") tone for free-field testing.
+/// The instantaneous frequency swings sinusoidally around `freq` by
+/// `±depth * freq` at `rate` Hz. Gating and channel routing are the same as
+/// `SineWave`: 48kHz, `channels` channels, only `on_channel` is driven.
+#[derive(Clone, Debug)]
+pub struct WarbleTone {
+    freq: f32,
+    rate: f32,
+    depth: f32,
+    phase: f32,
+    gate: Gate,
+}
+
+impl WarbleTone {
+    /// Builds a new `WarbleTone` centred on `freq`.
+    pub fn new(
+        freq: f32,
+        rate: f32,
+        depth: f32,
+        timing: Timing,
+        on_channel: u16,
+        channels: u16,
+    ) -> WarbleTone {
+        WarbleTone {
+            freq,
+            rate,
+            depth,
+            phase: 0.0,
+            gate: Gate::new(timing, on_channel, channels),
+        }
+    }
+
+    /// Instantaneous frequency at sample `n`.
+    fn frequency_at(&self, n: usize) -> f32 {
+        let t = n as f32 / 48000.0;
+        self.freq * (1.0 + self.depth * (2.0 * PI * self.rate * t).sin())
+    }
+}
+
+impl Iterator for WarbleTone {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        Some(match self.gate.next() {
+            Some((num_sample, gain)) => {
+                // integrate the frequency so the phase stays continuous
+                self.phase += 2.0 * PI * self.frequency_at(num_sample) / 48000.0;
+                if self.phase > 2.0 * PI {
+                    self.phase -= 2.0 * PI;
+                }
+                gain * self.phase.sin()
+            }
+            None => 0.0,
+        })
+    }
+}
+
+impl Source for WarbleTone {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.gate.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        48000
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warble_routing_and_frequency() {
+        let samples: Vec<f32> = WarbleTone::new(1000.0, 5.0, 0.05, Timing::default(), 1, 2)
+            .take(2 * 48000)
+            .collect();
+        let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+        let right: Vec<f32> = samples.iter().skip(1).step_by(2).copied().collect();
+        assert!(left.iter().all(|s| *s == 0.0));
+
+        // the first 0.4s burst holds two full modulation periods, so its mean
+        // frequency is the carrier
+        let crossings = right[..19200]
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        assert!(
+            (crossings as i32 - 400).abs() <= 2,
+            "{} crossings",
+            crossings
+        );
+        // and it is silent during the off time
+        assert!(right[19200..38400].iter().all(|s| *s == 0.0));
+    }
+}